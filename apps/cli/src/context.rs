@@ -1,7 +1,9 @@
 use dirs::{cache_dir, config_dir};
+use std::path::Path;
 use vfs::{PhysicalFS, VfsPath};
 
 pub struct Context {
+    /// The workspace root, or the working directory outside of a workspace
     pub root: VfsPath,
     pub args: Vec<String>,
     pub cache_dir: VfsPath,
@@ -9,7 +11,7 @@ pub struct Context {
 }
 
 impl Context {
-    pub fn new(args: Vec<String>) -> Self {
+    pub fn new(args: Vec<String>, root: &Path) -> Self {
         let user_cache_dir: VfsPath = PhysicalFS::new(cache_dir().unwrap()).into();
         let buri_cache_dir = user_cache_dir.join("buri").unwrap();
         buri_cache_dir.create_dir_all().unwrap();
@@ -18,7 +20,7 @@ impl Context {
         let buri_config_dir = user_config_dir.join("buri").unwrap();
         buri_config_dir.create_dir_all().unwrap();
         Self {
-            root: PhysicalFS::new(std::env::current_dir().unwrap()).into(),
+            root: PhysicalFS::new(root).into(),
             args,
             cache_dir: buri_cache_dir,
             config_dir: buri_config_dir,
@@ -1,7 +1,9 @@
 use config::ensure_version_is_in_config;
 use context::Context;
 use errors::CliError;
-use files::workspace_file::WORKSPACE_FILE_NAME;
+use files::workspace_file::{
+    find_workspace_root, WORKING_DIRECTORY_ENV_VAR, WORKSPACE_FILE_NAME, WORKSPACE_ROOT_ENV_VAR,
+};
 use impure::download_thor;
 use std::{env, os::unix::process::CommandExt, process::Command};
 use thor::{
@@ -65,14 +67,21 @@ pub async fn main() {
     let args = raw_args.collect::<Vec<String>>();
 
     let mut vio = Vio::new();
-    let context = Context::new(args);
+    // Buri can be run from any directory inside the workspace.
+    let working_directory = env::current_dir().unwrap();
+    let root = find_workspace_root(&working_directory).unwrap_or_else(|| working_directory.clone());
+    let context = Context::new(args, &root);
 
     let result = main_impl(context, &mut vio).await;
     match result {
         Ok((exec, args)) => {
             // Only works on Unix systems.
             // https://stackoverflow.com/a/53479765/11506995
-            let error = Command::new(exec).args(args).exec();
+            let error = Command::new(exec)
+                .args(args)
+                .env(WORKSPACE_ROOT_ENV_VAR, &root)
+                .env(WORKING_DIRECTORY_ENV_VAR, &working_directory)
+                .exec();
             // If we get here, the exec failed. See exec docs for
             // more details.
             println!("{:#?}", error);
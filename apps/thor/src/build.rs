@@ -0,0 +1,495 @@
+use crate::{
+    cache::{compute_cache_key, ActionCache, ActionOutput, CacheError},
+    context::Context,
+    errors::ThorError,
+};
+use build_graph::{
+    execute_in_parallel, topologically_sort_dep_graphs, BuildGraph, TargetFiles, TargetOutcome,
+};
+use std::{collections::HashMap, fmt, sync::Mutex};
+use target::Target;
+use vfs::{VfsError, VfsPath};
+use virtual_io::VirtualIo;
+
+#[derive(Debug)]
+pub enum BuildActionError {
+    MissingSourceFile(String),
+    /// Dependency whose cache key is not known yet
+    MissingDependencyDigest(Target),
+    VfsError(VfsError),
+    CacheError(CacheError),
+}
+
+impl fmt::Display for BuildActionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingSourceFile(path) => write!(f, "source file {path} does not exist"),
+            Self::MissingDependencyDigest(dependency) => {
+                write!(f, "dependency {dependency} has not been built")
+            }
+            Self::VfsError(error) => write!(f, "file system error: {error}"),
+            Self::CacheError(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+pub enum BuildResult {
+    Built,
+    UpToDate,
+}
+
+fn read_sources(
+    root: &VfsPath,
+    target_files: &TargetFiles,
+) -> Result<Vec<Vec<u8>>, BuildActionError> {
+    let package = root
+        .join(target_files.target.get_directories())
+        .map_err(BuildActionError::VfsError)?;
+    let mut sources = Vec::new();
+    for file in &target_files.files {
+        let source = package.join(file).map_err(BuildActionError::VfsError)?;
+        if !source.exists().map_err(BuildActionError::VfsError)? {
+            return Err(BuildActionError::MissingSourceFile(
+                source.as_str().trim_start_matches('/').to_string(),
+            ));
+        }
+        let mut contents = Vec::new();
+        source
+            .open_file()
+            .and_then(|mut file| Ok(file.read_to_end(&mut contents)?))
+            .map_err(BuildActionError::VfsError)?;
+        sources.push(contents);
+    }
+    Ok(sources)
+}
+
+/// Runs the build action for a single target, unless its output is already
+/// cached. There is no compiler to invoke yet, so for now building only
+/// ensures every source file can be read.
+pub(crate) fn run_build_action(
+    root: &VfsPath,
+    cache: &ActionCache,
+    digests: &Mutex<HashMap<String, String>>,
+    target_files: &TargetFiles,
+) -> Result<BuildResult, BuildActionError> {
+    let sources = read_sources(root, target_files)?;
+    let dependency_digests = {
+        let digests = digests.lock().unwrap();
+        target_files
+            .dependencies
+            .iter()
+            .map(|dependency| {
+                // Dependencies finish building before their dependents start.
+                digests
+                    .get(&dependency.to_string())
+                    .cloned()
+                    .ok_or_else(|| BuildActionError::MissingDependencyDigest(dependency.clone()))
+            })
+            .collect::<Result<Vec<String>, BuildActionError>>()?
+    };
+    let key = compute_cache_key(target_files, &sources, &dependency_digests)
+        .map_err(|error| BuildActionError::CacheError(CacheError::SerializationError(error)))?;
+    digests
+        .lock()
+        .unwrap()
+        .insert(target_files.target.to_string(), key.clone());
+
+    if cache.contains(&key) {
+        return Ok(BuildResult::UpToDate);
+    }
+    let output = ActionOutput {
+        target: target_files.target.to_string(),
+        files: target_files.files.clone(),
+    };
+    cache
+        .store(&key, &output)
+        .map_err(BuildActionError::CacheError)?;
+    Ok(BuildResult::Built)
+}
+
+/// Splits command line patterns into the targets to include and the targets
+/// to exclude.
+pub(crate) fn parse_patterns(
+    context: &Context,
+    patterns: &[String],
+) -> Result<(Vec<Target>, Vec<Target>), ThorError> {
+    let mut included = Vec::new();
+    let mut excluded = Vec::new();
+    for pattern in patterns {
+        // Patterns starting with `-` carve targets out of the other patterns.
+        let (targets, raw_target) = match pattern.strip_prefix('-') {
+            Some(raw_target) => (&mut excluded, raw_target),
+            None => (&mut included, pattern.as_str()),
+        };
+        targets.push(context.parse_label(raw_target)?);
+    }
+    Ok((included, excluded))
+}
+
+pub(crate) fn describe_build_outcome(
+    target_files: &TargetFiles,
+    outcome: &TargetOutcome<BuildResult, BuildActionError>,
+) -> String {
+    let target = &target_files.target;
+    match outcome {
+        TargetOutcome::Succeeded(BuildResult::Built) => format!("Built {target}"),
+        TargetOutcome::Succeeded(BuildResult::UpToDate) => format!("{target} is up to date"),
+        TargetOutcome::Failed(error) => format!("Failed to build {target}: {error}"),
+        TargetOutcome::Panicked(message) => {
+            format!("Failed to build {target}: build action panicked: {message}")
+        }
+        TargetOutcome::Skipped => {
+            format!("Skipped {target} because a dependency failed to build")
+        }
+    }
+}
+
+pub fn do_build(
+    context: &Context,
+    cache: &ActionCache,
+    vio: &mut impl VirtualIo,
+    patterns: &[String],
+    jobs: usize,
+) -> Result<(), ThorError> {
+    let (included, excluded) = parse_patterns(context, patterns)?;
+    let root = &context.root;
+    let graph = BuildGraph::new(
+        topologically_sort_dep_graphs(&included, &excluded, root)
+            .map_err(ThorError::DependencySortError)?,
+    );
+
+    let digests = Mutex::new(HashMap::new());
+    let mut failed = false;
+    execute_in_parallel(
+        &graph,
+        jobs,
+        |target_files| run_build_action(root, cache, &digests, target_files),
+        |target_files, outcome| {
+            failed |= matches!(
+                outcome,
+                TargetOutcome::Failed(_) | TargetOutcome::Panicked(_)
+            );
+            vio.println(describe_build_outcome(target_files, outcome));
+        },
+    );
+
+    if failed {
+        return Err(ThorError::BuildFailed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::create_file::create_test_file;
+    use vfs::MemoryFS;
+
+    fn build(root: &VfsPath, vio: &mut impl VirtualIo, patterns: &[&str]) -> Result<(), ThorError> {
+        let cache_dir: VfsPath = MemoryFS::new().into();
+        build_with_cache(root, &cache_dir, vio, patterns)
+    }
+
+    fn build_with_cache(
+        root: &VfsPath,
+        cache_dir: &VfsPath,
+        vio: &mut impl VirtualIo,
+        patterns: &[&str],
+    ) -> Result<(), ThorError> {
+        let cache = ActionCache::new(cache_dir).unwrap();
+        let patterns = patterns
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .collect::<Vec<String>>();
+        // A single job keeps the output order deterministic.
+        do_build(&Context::new(root.clone(), ""), &cache, vio, &patterns, 1)
+    }
+
+    fn create_two_library_package(root: &VfsPath) {
+        create_test_file(
+            root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//foo:b\"]
+            files = [\"a.buri\"]
+
+            [[library]]
+            name = \"b\"
+            files = [\"b.buri\"]
+            ",
+        );
+        create_test_file(root, "foo/a.buri", b"a");
+        create_test_file(root, "foo/b.buri", b"b");
+    }
+
+    #[test]
+    fn second_build_of_unchanged_tree_does_no_work() {
+        let root: VfsPath = MemoryFS::new().into();
+        let cache_dir: VfsPath = MemoryFS::new().into();
+        create_two_library_package(&root);
+        let mut vio = virtual_io::VioFakeBuilder::new()
+            .expect_stdout("Built foo:b\n")
+            .expect_stdout("Built foo:a\n")
+            .build();
+        build_with_cache(&root, &cache_dir, &mut vio, &["foo:a"]).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+
+        let mut vio = virtual_io::VioFakeBuilder::new()
+            .expect_stdout("foo:b is up to date\n")
+            .expect_stdout("foo:a is up to date\n")
+            .build();
+        build_with_cache(&root, &cache_dir, &mut vio, &["foo:a"]).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn changing_a_dependency_rebuilds_its_dependents() {
+        let root: VfsPath = MemoryFS::new().into();
+        let cache_dir: VfsPath = MemoryFS::new().into();
+        create_two_library_package(&root);
+        let mut vio = virtual_io::VioFakeBuilder::new().build();
+        build_with_cache(&root, &cache_dir, &mut vio, &["foo:a"]).unwrap();
+
+        create_test_file(&root, "foo/b.buri", b"changed");
+        let mut vio = virtual_io::VioFakeBuilder::new()
+            .expect_stdout("Built foo:b\n")
+            .expect_stdout("Built foo:a\n")
+            .build();
+        build_with_cache(&root, &cache_dir, &mut vio, &["foo:a"]).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn changing_a_dependent_does_not_rebuild_its_dependencies() {
+        let root: VfsPath = MemoryFS::new().into();
+        let cache_dir: VfsPath = MemoryFS::new().into();
+        create_two_library_package(&root);
+        let mut vio = virtual_io::VioFakeBuilder::new().build();
+        build_with_cache(&root, &cache_dir, &mut vio, &["foo:a"]).unwrap();
+
+        create_test_file(&root, "foo/a.buri", b"changed");
+        let mut vio = virtual_io::VioFakeBuilder::new()
+            .expect_stdout("foo:b is up to date\n")
+            .expect_stdout("Built foo:a\n")
+            .build();
+        build_with_cache(&root, &cache_dir, &mut vio, &["foo:a"]).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn builds_targets_in_dependency_order() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//foo:b\"]
+            files = [\"a.buri\"]
+
+            [[library]]
+            name = \"b\"
+            files = [\"b.buri\"]
+            ",
+        );
+        create_test_file(&root, "foo/a.buri", b"");
+        create_test_file(&root, "foo/b.buri", b"");
+        let mut vio = virtual_io::VioFakeBuilder::new()
+            .expect_stdout("Built foo:b\n")
+            .expect_stdout("Built foo:a\n")
+            .build();
+        build(&root, &mut vio, &["foo:a"]).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn builds_shared_dependencies_once() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//foo:c\"]
+
+            [[library]]
+            name = \"b\"
+            dependencies = [\"//foo:c\"]
+
+            [[library]]
+            name = \"c\"
+            ",
+        );
+        let mut vio = virtual_io::VioFakeBuilder::new()
+            .expect_stdout("Built foo:c\n")
+            .expect_stdout("Built foo:a\n")
+            .expect_stdout("Built foo:b\n")
+            .build();
+        build(&root, &mut vio, &["foo:a", "foo:b"]).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn skips_targets_matched_by_exclusion_patterns() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            ",
+        );
+        create_test_file(
+            &root,
+            "foo/legacy/BUILD.toml",
+            b"
+            [[library]]
+            name = \"old\"
+            ",
+        );
+        let mut vio = virtual_io::VioFakeBuilder::new()
+            .expect_stdout("Built foo:a\n")
+            .build();
+        build(&root, &mut vio, &["foo/...", "-foo/legacy/..."]).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn resolves_patterns_relative_to_the_working_directory() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            ",
+        );
+        create_test_file(
+            &root,
+            "foo/legacy/BUILD.toml",
+            b"
+            [[library]]
+            name = \"old\"
+            ",
+        );
+        let cache_dir: VfsPath = MemoryFS::new().into();
+        let cache = ActionCache::new(&cache_dir).unwrap();
+        let mut vio = virtual_io::VioFakeBuilder::new()
+            .expect_stdout("Built foo:a\n")
+            .build();
+        let patterns = [String::from("..."), String::from("-legacy:...")];
+        do_build(&Context::new(root, "foo"), &cache, &mut vio, &patterns, 1).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn errors_instead_of_panicking_when_a_dependency_was_not_built() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_two_library_package(&root);
+        let cache_dir: VfsPath = MemoryFS::new().into();
+        let cache = ActionCache::new(&cache_dir).unwrap();
+        let sorted = topologically_sort_dep_graphs(
+            &[target::parse::parse_target("foo:a").unwrap()],
+            &[],
+            &root,
+        )
+        .unwrap();
+        let result = run_build_action(&root, &cache, &Mutex::new(HashMap::new()), &sorted[1]);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "dependency foo:b has not been built"
+        );
+    }
+
+    #[test]
+    fn reports_failure_when_source_file_is_missing() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            files = [\"a.buri\"]
+            ",
+        );
+        let mut vio = virtual_io::VioFakeBuilder::new()
+            .expect_stdout("Failed to build foo:a: source file foo/a.buri does not exist\n")
+            .build();
+        let result = build(&root, &mut vio, &["foo:a"]);
+        assert!(matches!(result, Err(ThorError::BuildFailed)));
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn does_not_build_dependents_of_a_failed_target() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//foo:b\"]
+
+            [[library]]
+            name = \"b\"
+            files = [\"b.buri\"]
+            ",
+        );
+        let mut vio = virtual_io::VioFakeBuilder::new()
+            .expect_stdout("Failed to build foo:b: source file foo/b.buri does not exist\n")
+            .expect_stdout("Skipped foo:a because a dependency failed to build\n")
+            .build();
+        let result = build(&root, &mut vio, &["foo:a"]);
+        assert!(matches!(result, Err(ThorError::BuildFailed)));
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn keeps_building_targets_unaffected_by_a_failure() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            files = [\"a.buri\"]
+
+            [[library]]
+            name = \"b\"
+            ",
+        );
+        let mut vio = virtual_io::VioFakeBuilder::new()
+            .expect_stdout("Failed to build foo:a: source file foo/a.buri does not exist\n")
+            .expect_stdout("Built foo:b\n")
+            .build();
+        let result = build(&root, &mut vio, &["foo:a", "foo:b"]);
+        assert!(matches!(result, Err(ThorError::BuildFailed)));
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn errors_on_invalid_target_pattern() {
+        let root: VfsPath = MemoryFS::new().into();
+        let mut vio = virtual_io::VioFakeBuilder::new().build();
+        let result = build(&root, &mut vio, &["foo:bar:baz"]);
+        assert!(matches!(result, Err(ThorError::InvalidTarget(_, _))));
+    }
+
+    #[test]
+    fn errors_if_target_does_not_exist() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(&root, "foo/BUILD.toml", b"");
+        let mut vio = virtual_io::VioFakeBuilder::new().build();
+        let result = build(&root, &mut vio, &["foo:a"]);
+        assert!(matches!(result, Err(ThorError::DependencySortError(_))));
+    }
+}
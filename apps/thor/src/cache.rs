@@ -0,0 +1,258 @@
+use build_graph::TargetFiles;
+use files::build_file::{Library, TargetDefinition};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use vfs::{VfsError, VfsPath};
+
+// Anything built by a different toolchain must not be reused.
+const TOOLCHAIN_VERSION: &str = env!("CARGO_PKG_VERSION");
+const ACTIONS_DIRECTORY: &str = "actions";
+
+/// What a build action produced, stored in the cache under its key.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ActionOutput {
+    pub target: String,
+    pub files: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    VfsError(VfsError),
+    SerializationError(toml::ser::Error),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::VfsError(error) => write!(f, "file system error: {error}"),
+            Self::SerializationError(error) => {
+                write!(f, "could not serialize the action output: {error}")
+            }
+        }
+    }
+}
+
+pub struct ActionCache {
+    actions: VfsPath,
+}
+
+impl ActionCache {
+    pub fn new(cache_dir: &VfsPath) -> Result<Self, VfsError> {
+        let actions = cache_dir.join(ACTIONS_DIRECTORY)?;
+        actions.create_dir_all()?;
+        Ok(Self { actions })
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.actions
+            .join(key)
+            .and_then(|entry| entry.exists())
+            .unwrap_or(false)
+    }
+
+    pub fn store(&self, key: &str, output: &ActionOutput) -> Result<(), CacheError> {
+        let contents = toml::to_string(output).map_err(CacheError::SerializationError)?;
+        // Write to a temporary file first so an interrupted build never leaves
+        // behind a truncated entry that looks valid.
+        let write = || -> Result<(), VfsError> {
+            let temporary = self.actions.join(format!("{key}.tmp"))?;
+            temporary.create_file()?.write_all(contents.as_bytes())?;
+            let entry = self.actions.join(key)?;
+            if entry.exists()? {
+                entry.remove_file()?;
+            }
+            temporary.move_file(&entry)
+        };
+        write().map_err(CacheError::VfsError)
+    }
+}
+
+fn update_with_field(hasher: &mut Sha256, field: &[u8]) {
+    // Length prefixes keep adjacent fields from running into each other.
+    hasher.update((field.len() as u64).to_le_bytes());
+    hasher.update(field);
+}
+
+/// Computes the key a target's build output is cached under. `sources` holds
+/// the contents of each entry of `target_files.files`, in the same order, and
+/// `dependency_digests` the keys of its direct dependencies.
+pub fn compute_cache_key(
+    target_files: &TargetFiles,
+    sources: &[Vec<u8>],
+    dependency_digests: &[String],
+) -> Result<String, toml::ser::Error> {
+    let mut hasher = Sha256::new();
+    update_with_field(&mut hasher, TOOLCHAIN_VERSION.as_bytes());
+    update_with_field(&mut hasher, target_files.target.to_string().as_bytes());
+    // Equivalent spellings of the same dependency must not change the key.
+    let definition = match target_files
+        .definition
+        .canonicalize(target_files.target.get_directories())
+    {
+        // Who may depend on a library does not change what it builds into.
+        TargetDefinition::Library(library) => TargetDefinition::Library(Library {
+            dependents: None,
+            visibility: None,
+            ..library
+        }),
+        definition => definition,
+    };
+    update_with_field(&mut hasher, toml::to_string(&definition)?.as_bytes());
+    for (file, contents) in target_files.files.iter().zip(sources) {
+        update_with_field(&mut hasher, file.as_bytes());
+        update_with_field(&mut hasher, contents);
+    }
+    for digest in dependency_digests {
+        update_with_field(&mut hasher, digest.as_bytes());
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use target::parse::parse_target;
+    use vfs::MemoryFS;
+
+    fn read_entry(cache_dir: &VfsPath, key: &str) -> ActionOutput {
+        let contents = cache_dir
+            .join(ACTIONS_DIRECTORY)
+            .unwrap()
+            .join(key)
+            .unwrap()
+            .read_to_string()
+            .unwrap();
+        toml::from_str(&contents).unwrap()
+    }
+
+    fn target_files(files: &[&str]) -> TargetFiles {
+        target_files_with(files, |_| {})
+    }
+
+    /// Target files for `foo:bar`, after `edit` has changed its definition.
+    fn target_files_with(files: &[&str], edit: impl FnOnce(&mut Library)) -> TargetFiles {
+        let mut library = Library {
+            name: "bar".to_string(),
+            files: Some(files.iter().map(|file| file.to_string()).collect()),
+            dependencies: None,
+            dependents: None,
+            visibility: None,
+        };
+        edit(&mut library);
+        TargetFiles {
+            target: parse_target("foo:bar").unwrap(),
+            files: files.iter().map(|file| file.to_string()).collect(),
+            dependencies: Vec::new(),
+            definition: TargetDefinition::Library(library),
+        }
+    }
+
+    #[test]
+    fn key_is_stable_for_the_same_inputs() {
+        let target_files = target_files(&["a.buri"]);
+        let sources = Vec::from([b"hello".to_vec()]);
+        assert_eq!(
+            compute_cache_key(&target_files, &sources, &[]),
+            compute_cache_key(&target_files, &sources, &[])
+        );
+    }
+
+    #[test]
+    fn key_changes_when_a_source_file_changes() {
+        let target_files = target_files(&["a.buri"]);
+        assert_ne!(
+            compute_cache_key(&target_files, &[b"hello".to_vec()], &[]),
+            compute_cache_key(&target_files, &[b"world".to_vec()], &[])
+        );
+    }
+
+    #[test]
+    fn key_changes_when_a_source_file_is_renamed() {
+        let sources = Vec::from([b"hello".to_vec()]);
+        assert_ne!(
+            compute_cache_key(&target_files(&["a.buri"]), &sources, &[]),
+            compute_cache_key(&target_files(&["b.buri"]), &sources, &[])
+        );
+    }
+
+    #[test]
+    fn key_changes_when_the_library_definition_changes() {
+        let target_files_without_dependencies = target_files(&[]);
+        let target_files_with_dependencies = target_files_with(&[], |library| {
+            library.dependencies = Some(Vec::from([parse_target("baz:qux").unwrap()]))
+        });
+        assert_ne!(
+            compute_cache_key(&target_files_without_dependencies, &[], &[]),
+            compute_cache_key(&target_files_with_dependencies, &[], &[])
+        );
+    }
+
+    #[test]
+    fn key_ignores_dependents_and_visibility() {
+        let plain = target_files(&[]);
+        let restricted = target_files_with(&[], |library| {
+            library.dependents = Some(Vec::from([parse_target("baz:qux").unwrap()]));
+            library.visibility = Some(Vec::from([parse_target("baz:...").unwrap()]));
+        });
+        assert_eq!(
+            compute_cache_key(&plain, &[], &[]),
+            compute_cache_key(&restricted, &[], &[])
+        );
+    }
+
+    #[test]
+    fn key_ignores_how_dependencies_are_spelled() {
+        let relative = target_files_with(&[], |library| {
+            library.dependencies = Some(Vec::from([":baz".parse().unwrap()]))
+        });
+        let absolute = target_files_with(&[], |library| {
+            library.dependencies = Some(Vec::from(["//foo:baz".parse().unwrap()]))
+        });
+        assert_eq!(
+            compute_cache_key(&relative, &[], &[]),
+            compute_cache_key(&absolute, &[], &[])
+        );
+    }
+
+    #[test]
+    fn key_changes_when_a_dependency_digest_changes() {
+        let target_files = target_files(&[]);
+        assert_ne!(
+            compute_cache_key(&target_files, &[], &["abc".to_string()]),
+            compute_cache_key(&target_files, &[], &["def".to_string()])
+        );
+    }
+
+    #[test]
+    fn stores_and_retrieves_outputs() {
+        let cache_dir: VfsPath = MemoryFS::new().into();
+        let cache = ActionCache::new(&cache_dir).unwrap();
+        let output = ActionOutput {
+            target: "foo:bar".to_string(),
+            files: Vec::from(["a.buri".to_string()]),
+        };
+        assert!(!cache.contains("abc"));
+        cache.store("abc", &output).unwrap();
+        assert!(cache.contains("abc"));
+        assert_eq!(read_entry(&cache_dir, "abc"), output);
+        assert!(!cache_dir.join("actions/abc.tmp").unwrap().exists().unwrap());
+    }
+
+    #[test]
+    fn overwrites_existing_entries() {
+        let cache_dir: VfsPath = MemoryFS::new().into();
+        let cache = ActionCache::new(&cache_dir).unwrap();
+        let first = ActionOutput {
+            target: "foo:bar".to_string(),
+            files: Vec::new(),
+        };
+        let second = ActionOutput {
+            target: "foo:bar".to_string(),
+            files: Vec::from(["a.buri".to_string()]),
+        };
+        cache.store("abc", &first).unwrap();
+        cache.store("abc", &second).unwrap();
+        assert_eq!(read_entry(&cache_dir, "abc"), second);
+    }
+}
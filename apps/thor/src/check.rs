@@ -0,0 +1,186 @@
+use crate::{
+    context::Context,
+    errors::{describe_dependency_sort_error, ThorError},
+};
+use build_graph::{
+    check_dep_graph, check_source_files, find_dependents_mismatches, topologically_sort_dep_graph,
+};
+use virtual_io::VirtualIo;
+
+/// Checks that the dependency graph of `pattern` resolves, and once it does,
+/// that the source files of its targets are where they should be and that
+/// the `dependents` of the libraries it matches are up to date. Every problem
+/// is reported at once, sorted by file, unless `fail_fast` is set.
+pub fn do_check(
+    context: &Context,
+    vio: &mut impl VirtualIo,
+    pattern: &str,
+    fail_fast: bool,
+) -> Result<(), ThorError> {
+    let target = context.parse_label(pattern)?;
+    let root = &context.root;
+    let errors = if fail_fast {
+        topologically_sort_dep_graph(target.clone(), root)
+            .err()
+            .into_iter()
+            .collect()
+    } else {
+        check_dep_graph(target.clone(), root)
+    };
+    let mut problems = errors
+        .iter()
+        .map(describe_dependency_sort_error)
+        .collect::<Vec<String>>();
+    // Source files and dependents can only be worked out once the graph
+    // resolves.
+    if problems.is_empty() {
+        let targets = topologically_sort_dep_graph(target.clone(), root)
+            .map_err(ThorError::DependencySortError)?;
+        let mut errors = check_source_files(&targets, root);
+        errors.sort_by_key(|error| error.file());
+        problems.extend(errors.iter().map(describe_dependency_sort_error));
+        problems.extend(
+            find_dependents_mismatches(&target, root)
+                .map_err(ThorError::DependencySortError)?
+                .iter()
+                .map(|mismatch| mismatch.to_string()),
+        );
+        if fail_fast {
+            problems.truncate(1);
+        }
+    }
+    if problems.is_empty() {
+        vio.println("No problems found");
+        return Ok(());
+    }
+
+    for problem in &problems {
+        vio.println(problem);
+        vio.println("");
+    }
+    Err(ThorError::CheckFailed(problems.len()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::create_file::create_test_file;
+    use vfs::{MemoryFS, VfsPath};
+    use virtual_io::VioFakeBuilder;
+
+    fn broken_workspace() -> VfsPath {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//zed:b\", \"//foo:unknown\"]
+            ",
+        );
+        root
+    }
+
+    #[test]
+    fn reports_every_error_sorted_by_file() {
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("Target foo:unknown not found in foo/BUILD.toml\n\n")
+            .expect_stdout("Build file zed/BUILD.toml for target zed:b does not exist\n\n")
+            .build();
+        let result = do_check(
+            &Context::new(broken_workspace(), ""),
+            &mut vio,
+            "foo:a",
+            false,
+        );
+        assert!(matches!(result, Err(ThorError::CheckFailed(2))));
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn stops_at_the_first_error_when_failing_fast() {
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("Build file zed/BUILD.toml for target zed:b does not exist\n\n")
+            .build();
+        let result = do_check(
+            &Context::new(broken_workspace(), ""),
+            &mut vio,
+            "foo:a",
+            true,
+        );
+        assert!(matches!(result, Err(ThorError::CheckFailed(1))));
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn reports_out_of_date_dependents() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependents = [\":c\"]
+
+            [[library]]
+            name = \"b\"
+            dependencies = [\":a\"]
+
+            [[library]]
+            name = \"c\"
+            ",
+        );
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout(
+                "Dependents of foo:a in foo/BUILD.toml do not match the targets that depend on it
+  missing: foo:b
+  not a dependent: foo:c
+  help: run `buri fix` to update them\n\n",
+            )
+            .build();
+        let result = do_check(&Context::new(root, ""), &mut vio, "...", false);
+        assert!(matches!(result, Err(ThorError::CheckFailed(1))));
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn reports_problems_with_source_files() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            files = [\"a.buri\", \"../b.buri\"]
+            ",
+        );
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("Source file ../b.buri of foo:a is outside its package\n\n")
+            .expect_stdout("Source file foo/a.buri of foo:a does not exist\n\n")
+            .build();
+        let result = do_check(&Context::new(root, ""), &mut vio, "...", false);
+        assert!(matches!(result, Err(ThorError::CheckFailed(2))));
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn reports_when_nothing_is_wrong() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            ",
+        );
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("No problems found\n")
+            .build();
+        do_check(&Context::new(root, ""), &mut vio, "...", false).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+}
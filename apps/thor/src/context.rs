@@ -0,0 +1,118 @@
+use crate::errors::ThorError;
+use files::workspace_file::{
+    find_workspace_root, WORKING_DIRECTORY_ENV_VAR, WORKSPACE_ROOT_ENV_VAR,
+};
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+use target::{
+    parse::{parse_label, parse_target},
+    Target,
+};
+use vfs::{PhysicalFS, VfsPath};
+
+/// Where in the workspace thor is running.
+pub struct Context {
+    /// The directory holding `WORKSPACE.toml`
+    pub root: VfsPath,
+    /// Package of the working directory relative to the root, e.g. `foo/bar`.
+    /// Empty at the root.
+    pub package: String,
+}
+
+impl Context {
+    pub fn new(root: VfsPath, package: &str) -> Self {
+        Self {
+            root,
+            package: package.to_string(),
+        }
+    }
+
+    /// The context `buri` was run in. The CLI passes the workspace root and
+    /// working directory through the environment; when thor is run directly
+    /// they are worked out from the current directory instead.
+    pub fn from_environment() -> Result<Self, ThorError> {
+        let working_directory = env::var_os(WORKING_DIRECTORY_ENV_VAR)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| env::current_dir().unwrap());
+        let root = env::var_os(WORKSPACE_ROOT_ENV_VAR)
+            .map(PathBuf::from)
+            .or_else(|| find_workspace_root(&working_directory))
+            .unwrap_or_else(|| working_directory.clone());
+        Self::from_directories(&root, &working_directory)
+    }
+
+    /// The package of `working_directory` is its path relative to `root`.
+    /// Outside of the workspace the package is the root package. Directories
+    /// whose path could not be written in a label are not valid packages.
+    pub fn from_directories(root: &Path, working_directory: &Path) -> Result<Self, ThorError> {
+        let package = working_directory
+            .strip_prefix(root)
+            .unwrap_or(Path::new(""))
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if !package.is_empty() {
+            parse_target(&format!("{package}:x"))
+                .map_err(|error| ThorError::InvalidPackage(package.clone(), error))?;
+        }
+        Ok(Self::new(PhysicalFS::new(root).into(), &package))
+    }
+
+    /// Parses a label given on the command line. Labels are relative to the
+    /// working directory's package unless they start with `//`.
+    pub fn parse_label(&self, label: &str) -> Result<Target, ThorError> {
+        parse_label(label, &self.package)
+            .map_err(|error| ThorError::InvalidTarget(label.to_string(), error))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use vfs::MemoryFS;
+
+    #[test]
+    fn parses_labels_relative_to_the_package() {
+        let context = Context::new(MemoryFS::new().into(), "foo");
+        assert_eq!(context.parse_label(":bar").unwrap().to_string(), "foo:bar");
+        assert_eq!(
+            context.parse_label("//baz:qux").unwrap().to_string(),
+            "baz:qux"
+        );
+        assert!(matches!(
+            context.parse_label("/bar"),
+            Err(ThorError::InvalidTarget(_, _))
+        ));
+    }
+
+    #[test]
+    fn finds_the_package_of_the_working_directory() {
+        let root = Path::new("/workspace");
+        let context = Context::from_directories(root, &root.join("foo").join("bar")).unwrap();
+        assert_eq!(context.package, "foo/bar");
+        assert_eq!(Context::from_directories(root, root).unwrap().package, "");
+        assert_eq!(
+            Context::from_directories(root, Path::new("/elsewhere"))
+                .unwrap()
+                .package,
+            ""
+        );
+    }
+
+    #[test]
+    fn rejects_directories_that_are_not_valid_packages() {
+        let root = Path::new("/workspace");
+        for directory in [".github/workflows", "my dir"] {
+            assert!(
+                matches!(
+                    Context::from_directories(root, &root.join(directory)),
+                    Err(ThorError::InvalidPackage(package, _)) if package == directory
+                ),
+                "{directory}"
+            );
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use build_graph::DependencySortError;
+use files::build_file::build_file_location;
+use query::QueryError;
+use std::fmt::{Display, Formatter, Result};
+use target::parse::TargetParseError;
+use vfs::VfsError;
+
+#[derive(Debug)]
+pub enum ThorError {
+    VfsError(VfsError),
+    /// Raw target pattern, parse error
+    InvalidTarget(String, TargetParseError),
+    /// Package of the working directory, why it is not a valid package
+    InvalidPackage(String, TargetParseError),
+    DependencySortError(DependencySortError),
+    BuildFailed,
+    /// Shard index, shard count
+    InvalidShard(usize, usize),
+    TestsFailed,
+    /// Number of problems found
+    CheckFailed(usize),
+    QueryError(QueryError),
+    /// File name, serialization error
+    SerializationError(String, toml::ser::Error),
+    /// File name, why it could not be edited
+    EditError(String, toml_edit::TomlError),
+    /// Number of files that are not formatted
+    FormatCheckFailed(usize),
+    /// The user has no cache directory to keep build outputs in
+    NoCacheDirectory,
+    /// Path given for a report that does not name a file
+    InvalidReportPath(String),
+}
+
+pub fn describe_dependency_sort_error(error: &DependencySortError) -> String {
+    match error {
+        // Cycles are printed one edge per line so each one can be traced back
+        // to the build file that declares it.
+        DependencySortError::CyclicDependency(cycle) => {
+            let mut description = "Dependency cycle detected:".to_string();
+            for edge in cycle.windows(2) {
+                description.push_str(&format!(
+                    "\n  {} -> {} (declared in {})",
+                    edge[0],
+                    edge[1],
+                    build_file_location(&edge[0])
+                ));
+            }
+            description
+        }
+        DependencySortError::TargetNotFoundInBuildFile(_, suggestions)
+        | DependencySortError::BuildFileNotFound(_, suggestions)
+            if !suggestions.is_empty() =>
+        {
+            let suggestions = suggestions
+                .iter()
+                .map(|target| target.to_string())
+                .collect::<Vec<String>>();
+            format!(
+                "{error}\n  help: did you mean {}?",
+                match suggestions.as_slice() {
+                    [suggestion] => suggestion.clone(),
+                    _ => format!("one of {}", suggestions.join(", ")),
+                }
+            )
+        }
+        error => error.to_string(),
+    }
+}
+
+impl Display for ThorError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::VfsError(error) => format!("File system error: {error}"),
+                Self::InvalidTarget(target, error) => format!(
+                    "Invalid target {target}: {error}\n{}",
+                    error
+                        .underline(target)
+                        .lines()
+                        .map(|line| format!("  {line}"))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                ),
+                Self::InvalidPackage(package, error) => format!(
+                    "Cannot run in {package}, it is not a valid package: {error}\n{}",
+                    error
+                        .underline(package)
+                        .lines()
+                        .map(|line| format!("  {line}"))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                ),
+                Self::DependencySortError(error) => describe_dependency_sort_error(error),
+                Self::BuildFailed => "Build failed".to_string(),
+                Self::InvalidShard(index, count) => format!(
+                    "Shard index {index} is out of range for {count} shards, it must be less than the shard count"
+                ),
+                Self::TestsFailed => "Tests failed".to_string(),
+                Self::CheckFailed(1) => "Found 1 problem".to_string(),
+                Self::CheckFailed(count) => format!("Found {count} problems"),
+                Self::QueryError(QueryError::DependencySortError(error)) =>
+                    describe_dependency_sort_error(error),
+                Self::QueryError(error) => error.to_string(),
+                Self::SerializationError(file, error) => format!("Could not write {file}: {error}"),
+                Self::EditError(file, error) => format!("Could not edit {file}: {error}"),
+                Self::FormatCheckFailed(1) =>
+                    "1 file is not formatted, run `buri fmt` to format it".to_string(),
+                Self::FormatCheckFailed(count) =>
+                    format!("{count} files are not formatted, run `buri fmt` to format them"),
+                Self::NoCacheDirectory =>
+                    "Could not find a cache directory to store build outputs in".to_string(),
+                Self::InvalidReportPath(path) =>
+                    format!("Cannot write a report to {path}, it is not a file path"),
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use target::parse::parse_target;
+
+    #[test]
+    fn renders_each_edge_of_a_cycle() {
+        let cycle = ["foo:a", "bar/baz:b", "foo:a"]
+            .iter()
+            .map(|target| parse_target(target).unwrap())
+            .collect();
+        let error = ThorError::DependencySortError(DependencySortError::CyclicDependency(cycle));
+        assert_eq!(
+            error.to_string(),
+            "Dependency cycle detected:
+  foo:a -> bar/baz:b (declared in foo/BUILD.toml)
+  bar/baz:b -> foo:a (declared in bar/baz/BUILD.toml)"
+        );
+    }
+
+    #[test]
+    fn suggests_similar_targets() {
+        let error = ThorError::DependencySortError(DependencySortError::TargetNotFoundInBuildFile(
+            parse_target("foo:bza").unwrap(),
+            Vec::from([parse_target("foo:baz").unwrap()]),
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Target foo:bza not found in foo/BUILD.toml\n  help: did you mean foo:baz?"
+        );
+        let error = ThorError::DependencySortError(DependencySortError::BuildFileNotFound(
+            parse_target("fo:baz").unwrap(),
+            Vec::from([
+                parse_target("foo:baz").unwrap(),
+                parse_target("bo:baz").unwrap(),
+            ]),
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Build file fo/BUILD.toml for target fo:baz does not exist
+  help: did you mean one of foo:baz, bo:baz?"
+        );
+    }
+
+    #[test]
+    fn underlines_invalid_targets() {
+        let error = parse_target("foo:b$d").unwrap_err();
+        assert_eq!(
+            ThorError::InvalidTarget("foo:b$d".to_string(), error).to_string(),
+            "Invalid target foo:b$d: target contains an illegal character `$`
+  foo:b$d
+       ^"
+        );
+    }
+
+    #[test]
+    fn omits_help_without_suggestions() {
+        let error = ThorError::DependencySortError(DependencySortError::TargetNotFoundInBuildFile(
+            parse_target("foo:bza").unwrap(),
+            Vec::new(),
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Target foo:bza not found in foo/BUILD.toml"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{context::Context, errors::ThorError};
+use build_graph::{find_dependents_mismatches, DependentsMismatch};
+use std::{collections::BTreeMap, io::Write};
+use target::parse::parse_label;
+use toml_edit::{Document, Item, RawString, Value};
+use virtual_io::VirtualIo;
+
+/// Rewrites the `dependents` of `mismatch.library` in `document`. Entries
+/// that are still dependents keep their spelling and the missing ones are
+/// added after them, so comments and formatting are left alone.
+fn update_dependents(document: &mut Document, mismatch: &DependentsMismatch) {
+    let package = mismatch.library.get_directories();
+    let Some(libraries) = document
+        .get_mut("library")
+        .and_then(Item::as_array_of_tables_mut)
+    else {
+        return;
+    };
+    let Some(dependents) = libraries
+        .iter_mut()
+        .find(|library| library.get("name").and_then(Item::as_str) == Some(mismatch.library.name()))
+        .and_then(|library| library.get_mut("dependents"))
+        .and_then(Item::as_array_mut)
+    else {
+        return;
+    };
+    dependents.retain(|dependent| {
+        dependent
+            .as_str()
+            .and_then(|label| parse_label(label, package).ok())
+            .is_some_and(|dependent| mismatch.actual.contains(&dependent))
+    });
+    // New entries are indented like the existing ones, so multi-line arrays
+    // stay one entry per line.
+    let indent = dependents
+        .iter()
+        .filter_map(|dependent| dependent.decor().prefix().and_then(RawString::as_str))
+        .find_map(|prefix| prefix.rsplit_once('\n'))
+        .map_or(" ".to_string(), |(_, indent)| format!("\n{indent}"));
+    for dependent in &mismatch.missing {
+        dependents.push_formatted(
+            Value::from(dependent.label_in(package)).decorated(indent.as_str(), ""),
+        );
+    }
+}
+
+/// Brings the `dependents` of every library matched by `pattern` in line with
+/// the targets that actually depend on it.
+pub fn do_fix(context: &Context, vio: &mut impl VirtualIo, pattern: &str) -> Result<(), ThorError> {
+    let target = context.parse_label(pattern)?;
+    let root = &context.root;
+    let mismatches =
+        find_dependents_mismatches(&target, root).map_err(ThorError::DependencySortError)?;
+    if mismatches.is_empty() {
+        vio.println("Nothing to fix");
+        return Ok(());
+    }
+
+    let mut by_file = BTreeMap::<String, Vec<&DependentsMismatch>>::new();
+    for mismatch in &mismatches {
+        by_file.entry(mismatch.file()).or_default().push(mismatch);
+    }
+    for (file, mismatches) in by_file {
+        let build_file = root.join(&file).map_err(ThorError::VfsError)?;
+        let contents = build_file.read_to_string().map_err(ThorError::VfsError)?;
+        let mut document = contents
+            .parse::<Document>()
+            .map_err(|error| ThorError::EditError(file.clone(), error))?;
+        for mismatch in mismatches {
+            update_dependents(&mut document, mismatch);
+            vio.println(format!(
+                "Updated dependents of {} in {file}",
+                mismatch.library
+            ));
+        }
+        build_file.remove_file().map_err(ThorError::VfsError)?;
+        build_file
+            .create_file()
+            .and_then(|mut file| Ok(write!(file, "{document}")?))
+            .map_err(ThorError::VfsError)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::create_file::create_test_file;
+    use vfs::{MemoryFS, VfsPath};
+    use virtual_io::VioFakeBuilder;
+
+    fn workspace() -> VfsPath {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "lib/BUILD.toml",
+            b"# Shared code
+[[library]]
+name = \"a\"
+# Keep in sync with the apps
+dependents = [
+    # the main app
+    \"//app:main\",
+    \"//old:gone\",
+]
+
+[[library]]
+name = \"b\"
+dependencies = [\":a\"]
+",
+        );
+        create_test_file(
+            &root,
+            "app/BUILD.toml",
+            b"
+            [[binary]]
+            name = \"main\"
+            entry_point = \"main.buri\"
+            files = [\"main.buri\"]
+            dependencies = [\"//lib:a\"]
+            ",
+        );
+        root
+    }
+
+    #[test]
+    fn rewrites_dependents_keeping_comments() {
+        let root = workspace();
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("Updated dependents of lib:a in lib/BUILD.toml\n")
+            .build();
+        do_fix(&Context::new(root.clone(), ""), &mut vio, "...").unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+
+        let contents = root
+            .join("lib/BUILD.toml")
+            .unwrap()
+            .read_to_string()
+            .unwrap();
+        assert!(contents.starts_with(
+            "# Shared code
+[[library]]
+name = \"a\"
+# Keep in sync with the apps
+dependents = [
+    # the main app
+    \"//app:main\",
+    \":b\",
+]
+"
+        ));
+    }
+
+    #[test]
+    fn leaves_nothing_to_fix_afterwards() {
+        let root = workspace();
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("Updated dependents of lib:a in lib/BUILD.toml\n")
+            .expect_stdout("Nothing to fix\n")
+            .build();
+        let context = Context::new(root, "");
+        do_fix(&context, &mut vio, "...").unwrap();
+        do_fix(&context, &mut vio, "...").unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+}
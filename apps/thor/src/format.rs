@@ -0,0 +1,488 @@
+use crate::{context::Context, errors::ThorError};
+use files::{
+    build_file::{TargetKind, BUILD_FILE_NAME},
+    workspace_file::WORKSPACE_FILE_NAME,
+};
+use std::io::Write;
+use target::parse::parse_label;
+use toml_edit::{Array, Document, Item, RawString, Table, TomlError, Value};
+use vfs::VfsPath;
+use virtual_io::VirtualIo;
+
+/// Arrays that would make their line longer than this are wrapped, one entry
+/// per line.
+const MAX_LINE_LENGTH: usize = 80;
+const INDENT: &str = "    ";
+/// The order keys are written in. Keys not listed keep their order after
+/// these.
+const KEY_ORDER: [&str; 7] = [
+    "name",
+    "entry_point",
+    "executable",
+    "files",
+    "dependencies",
+    "dependents",
+    "visibility",
+];
+/// Keys holding target labels, which are canonicalized, sorted and
+/// deduplicated.
+const LABEL_KEYS: [&str; 3] = ["dependencies", "dependents", "visibility"];
+
+/// The comment on `line`, if it holds one.
+fn comment(line: &str) -> Option<&str> {
+    Some(line.trim()).filter(|line| line.starts_with('#'))
+}
+
+/// Splits the whitespace in front of an item into the comment that ends the
+/// previous line, if any, and the comments on the lines above the item.
+fn split_comments(raw: Option<&RawString>) -> (Option<String>, Vec<String>) {
+    let raw = raw.and_then(RawString::as_str).unwrap_or_default();
+    let (same_line, above) = match raw.split_once('\n') {
+        Some((same_line, above)) => (comment(same_line), above),
+        None => (None, raw),
+    };
+    (
+        same_line.map(str::to_string),
+        above
+            .lines()
+            .filter_map(comment)
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// Every comment in a piece of whitespace, one per line.
+fn comments(raw: Option<&RawString>) -> Vec<String> {
+    let (same_line, above) = split_comments(raw);
+    same_line.into_iter().chain(above).collect()
+}
+
+fn comment_lines(comments: &[String], indent: &str) -> String {
+    comments
+        .iter()
+        .map(|comment| format!("{indent}{comment}\n"))
+        .collect()
+}
+
+/// A comment at the end of a line, or nothing.
+fn end_of_line_comment(raw: Option<&RawString>) -> String {
+    comments(raw)
+        .first()
+        .map(|comment| format!(" {comment}"))
+        .unwrap_or_default()
+}
+
+struct Entry {
+    value: Value,
+    /// Comment at the end of the entry's line
+    trailing_comment: Option<String>,
+    /// Comments on the lines above the entry
+    comments: Vec<String>,
+}
+
+/// Lays `array` out on one line if it fits and has no comments, and one entry
+/// per line otherwise. `width` is how much of the line comes before the array.
+/// When `labels` is set, labels are written in their canonical form for
+/// `package`, `:name` inside it and `//directories:name` anywhere else, then
+/// sorted and deduplicated.
+fn format_array(array: &mut Array, width: usize, package: &str, labels: bool) {
+    let mut entries: Vec<Entry> = Vec::new();
+    for value in array.iter() {
+        let (trailing_comment, comments) = split_comments(value.decor().prefix());
+        // A comment on the same line as the separator before this entry ends
+        // the line of the entry before it.
+        if let (Some(comment), Some(previous)) = (trailing_comment, entries.last_mut()) {
+            previous.trailing_comment = Some(comment);
+        }
+        let mut value = value.clone();
+        value.decor_mut().clear();
+        entries.push(Entry {
+            value,
+            trailing_comment: None,
+            comments,
+        });
+    }
+    let (last_comment, dangling_comments) = split_comments(Some(array.trailing()));
+    if let (Some(comment), Some(last)) = (last_comment, entries.last_mut()) {
+        last.trailing_comment = Some(comment);
+    }
+
+    if labels {
+        let label = |entry: &Entry| {
+            entry
+                .value
+                .as_str()
+                .and_then(|label| parse_label(label, package).ok())
+        };
+        for entry in &mut entries {
+            if let Some(target) = label(entry) {
+                entry.value = Value::from(target.label_in(package));
+            }
+        }
+        entries.sort_by_key(|entry| entry.value.to_string());
+        entries.dedup_by(|duplicate, kept| {
+            let same = match (label(duplicate), label(kept)) {
+                (Some(duplicate), Some(kept)) => duplicate == kept,
+                _ => duplicate.value.to_string() == kept.value.to_string(),
+            };
+            if same {
+                kept.comments.append(&mut duplicate.comments);
+                if kept.trailing_comment.is_none() {
+                    kept.trailing_comment = duplicate.trailing_comment.take();
+                }
+            }
+            same
+        });
+    }
+
+    let has_comments = !dangling_comments.is_empty()
+        || entries
+            .iter()
+            .any(|entry| entry.trailing_comment.is_some() || !entry.comments.is_empty());
+    let one_line_width = width
+        + 2
+        + entries
+            .iter()
+            .map(|entry| entry.value.to_string().len() + 2)
+            .sum::<usize>();
+    let wrap = has_comments || one_line_width > MAX_LINE_LENGTH;
+
+    let mut formatted = Array::new();
+    let mut previous_comment = None;
+    for (index, mut entry) in entries.into_iter().enumerate() {
+        let prefix = if wrap {
+            format!(
+                "{}\n{}{INDENT}",
+                previous_comment
+                    .take()
+                    .map(|comment| format!(" {comment}"))
+                    .unwrap_or_default(),
+                comment_lines(&entry.comments, INDENT)
+            )
+        } else if index == 0 {
+            String::new()
+        } else {
+            " ".to_string()
+        };
+        entry.value.decor_mut().set_prefix(prefix);
+        entry.value.decor_mut().set_suffix("");
+        previous_comment = entry.trailing_comment;
+        formatted.push_formatted(entry.value);
+    }
+    if wrap && !formatted.is_empty() {
+        formatted.set_trailing_comma(true);
+        formatted.set_trailing(format!(
+            "{}\n{}",
+            previous_comment
+                .map(|comment| format!(" {comment}"))
+                .unwrap_or_default(),
+            comment_lines(&dangling_comments, INDENT)
+        ));
+    }
+    *formatted.decor_mut() = array.decor().clone();
+    *array = formatted;
+}
+
+/// Orders the keys of `table` and lays out each key value pair as
+/// `key = value`, keeping comments.
+fn format_table(table: &mut Table, package: &str) {
+    let rank = |key: &str| {
+        KEY_ORDER
+            .iter()
+            .position(|known| *known == key)
+            .unwrap_or(KEY_ORDER.len())
+    };
+    table.sort_values_by(|a, _, b, _| rank(a.get()).cmp(&rank(b.get())));
+
+    let keys = table
+        .iter()
+        .filter(|(_, item)| item.is_value())
+        .map(|(key, _)| key.to_string())
+        .collect::<Vec<String>>();
+    for key in keys {
+        if let Some(decor) = table.key_decor_mut(&key) {
+            let above = comments(decor.prefix());
+            decor.set_prefix(comment_lines(&above, ""));
+            decor.set_suffix(" ");
+        }
+        let Some(value) = table.get_mut(&key).and_then(Item::as_value_mut) else {
+            continue;
+        };
+        if let Value::Array(array) = value {
+            let width = key.len() + " = ".len();
+            format_array(array, width, package, LABEL_KEYS.contains(&key.as_str()));
+        }
+        let suffix = end_of_line_comment(value.decor().suffix());
+        value.decor_mut().set_prefix(" ");
+        value.decor_mut().set_suffix(suffix);
+    }
+}
+
+fn format_document(contents: &str, package: &str) -> Result<String, TomlError> {
+    let mut document = contents.parse::<Document>()?;
+    format_table(document.as_table_mut(), package);
+
+    let mut first = !document.iter().any(|(_, item)| item.is_value());
+    let mut position = 0;
+    for kind in TargetKind::ALL {
+        let Some(tables) = document
+            .get_mut(kind.as_str())
+            .and_then(Item::as_array_of_tables_mut)
+        else {
+            continue;
+        };
+        for table in tables.iter_mut() {
+            // Targets are written grouped by kind, in the order they were
+            // declared in.
+            table.set_position(position);
+            position += 1;
+            format_table(table, package);
+            let above = comments(table.decor().prefix());
+            let separator = if first { "" } else { "\n" };
+            first = false;
+            let suffix = end_of_line_comment(table.decor().suffix());
+            table
+                .decor_mut()
+                .set_prefix(format!("{separator}{}", comment_lines(&above, "")));
+            table.decor_mut().set_suffix(suffix);
+        }
+    }
+
+    let dangling = comments(Some(document.trailing()));
+    document.set_trailing(if dangling.is_empty() {
+        String::new()
+    } else {
+        format!("\n{}", comment_lines(&dangling, ""))
+    });
+    Ok(document.to_string())
+}
+
+/// Formats the contents of the build file of `package`.
+pub fn format_build_file(contents: &str, package: &str) -> Result<String, TomlError> {
+    format_document(contents, package)
+}
+
+pub fn format_workspace_file(contents: &str) -> Result<String, TomlError> {
+    format_document(contents, "")
+}
+
+/// Every build file in the workspace, and the workspace file, in path order.
+fn files_to_format(root: &VfsPath) -> Result<Vec<VfsPath>, ThorError> {
+    let mut files = Vec::new();
+    for path in root.walk_dir().map_err(ThorError::VfsError)? {
+        let path = path.map_err(ThorError::VfsError)?;
+        if path.filename() == BUILD_FILE_NAME && path.is_file().map_err(ThorError::VfsError)? {
+            files.push(path);
+        }
+    }
+    let workspace_file = root
+        .join(WORKSPACE_FILE_NAME)
+        .map_err(ThorError::VfsError)?;
+    if workspace_file.is_file().map_err(ThorError::VfsError)? {
+        files.push(workspace_file);
+    }
+    files.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    Ok(files)
+}
+
+/// Rewrites every `BUILD.toml` and the `WORKSPACE.toml` in the workspace into
+/// their canonical layout. With `check` set, nothing is written and the files
+/// that would change are listed instead.
+pub fn do_fmt(context: &Context, vio: &mut impl VirtualIo, check: bool) -> Result<(), ThorError> {
+    let root = &context.root;
+    let mut changed = 0;
+    for file in files_to_format(root)? {
+        let location = file
+            .as_str()
+            .strip_prefix(root.as_str())
+            .unwrap_or_default()
+            .trim_start_matches('/')
+            .to_string();
+        let contents = file.read_to_string().map_err(ThorError::VfsError)?;
+        let formatted = if file.filename() == WORKSPACE_FILE_NAME {
+            format_workspace_file(&contents)
+        } else {
+            let package = location
+                .strip_suffix(BUILD_FILE_NAME)
+                .unwrap_or_default()
+                .trim_end_matches('/');
+            format_build_file(&contents, package)
+        }
+        .map_err(|error| ThorError::EditError(location.clone(), error))?;
+        if formatted == contents {
+            continue;
+        }
+
+        changed += 1;
+        if check {
+            vio.println(format!("{location} is not formatted"));
+            continue;
+        }
+        file.remove_file().map_err(ThorError::VfsError)?;
+        file.create_file()
+            .and_then(|mut file| Ok(file.write_all(formatted.as_bytes())?))
+            .map_err(ThorError::VfsError)?;
+        vio.println(format!("Formatted {location}"));
+    }
+
+    if changed == 0 {
+        vio.println("All files are formatted");
+    } else if check {
+        return Err(ThorError::FormatCheckFailed(changed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::create_file::create_test_file;
+    use vfs::MemoryFS;
+    use virtual_io::VioFakeBuilder;
+
+    #[test]
+    fn formats_build_files_canonically() {
+        let contents = "
+            [[test]]
+            name = \"app_test\"
+            files = [\"app_test.buri\"]
+            dependencies=[\":app\"]
+
+            # The library
+            [[library]]
+            dependents = [ \"//app/main\" ]
+            dependencies = [\"//lib/b\", \"//app:c\", \":c\", \"//lib/b:b\", \"//lib/a\"]
+            name = \"lib\"  # keep this name
+
+            [[binary]]
+            name = \"app\"
+            entry_point = \"main.buri\"
+            files = [\"main.buri\", \"flags.buri\", \"commands.buri\", \"environment.buri\", \"interactive.buri\"]
+            dependencies = [
+                \":lib\",
+            ]
+            # end of file
+        ";
+        assert_eq!(
+            format_build_file(contents, "app").unwrap(),
+            "# The library
+[[library]]
+name = \"lib\" # keep this name
+dependencies = [\"//lib/a:a\", \"//lib/b:b\", \":c\"]
+dependents = [\"//app/main:main\"]
+
+[[binary]]
+name = \"app\"
+entry_point = \"main.buri\"
+files = [
+    \"main.buri\",
+    \"flags.buri\",
+    \"commands.buri\",
+    \"environment.buri\",
+    \"interactive.buri\",
+]
+dependencies = [\":lib\"]
+
+[[test]]
+name = \"app_test\"
+files = [\"app_test.buri\"]
+dependencies = [\":app\"]
+
+# end of file
+"
+        );
+    }
+
+    #[test]
+    fn keeps_comments_on_wrapped_arrays() {
+        let contents = "[[library]]
+name = \"lib\"
+dependencies = [
+    # needed for strings
+    \"//util:strings\",
+    \":b\", # local
+]
+";
+        assert_eq!(
+            format_build_file(contents, "").unwrap(),
+            "[[library]]
+name = \"lib\"
+dependencies = [
+    # needed for strings
+    \"//util:strings\",
+    \":b\", # local
+]
+"
+        );
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let contents = "
+            [[library]]
+            name = \"lib\"
+            dependencies = [\"//b\", \"//a\", # why
+            ]
+        ";
+        let once = format_build_file(contents, "lib").unwrap();
+        assert_eq!(format_build_file(&once, "lib").unwrap(), once);
+    }
+
+    #[test]
+    fn formats_the_workspace_file() {
+        assert_eq!(
+            format_workspace_file("  name   =   \"buri\"\n\n").unwrap(),
+            "name = \"buri\"\n"
+        );
+    }
+
+    fn workspace() -> VfsPath {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(&root, "WORKSPACE.toml", b"name = \"buri\"\n");
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"[[library]]\nname = \"a\"\ndependencies = [\"//foo:b\"]\n",
+        );
+        create_test_file(&root, "bar/BUILD.toml", b"[[library]]\nname = \"b\"\n");
+        root
+    }
+
+    #[test]
+    fn check_lists_files_that_would_change() {
+        let root = workspace();
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("foo/BUILD.toml is not formatted\n")
+            .build();
+        let result = do_fmt(&Context::new(root.clone(), ""), &mut vio, true);
+        assert!(matches!(result, Err(ThorError::FormatCheckFailed(1))));
+        assert_eq!(vio.get_actual(), vio.get_expected());
+        assert_eq!(
+            root.join("foo/BUILD.toml")
+                .unwrap()
+                .read_to_string()
+                .unwrap(),
+            "[[library]]\nname = \"a\"\ndependencies = [\"//foo:b\"]\n"
+        );
+    }
+
+    #[test]
+    fn rewrites_files_that_are_not_formatted() {
+        let root = workspace();
+        let context = Context::new(root.clone(), "");
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("Formatted foo/BUILD.toml\n")
+            .expect_stdout("All files are formatted\n")
+            .build();
+        do_fmt(&context, &mut vio, false).unwrap();
+        do_fmt(&context, &mut vio, true).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+        assert_eq!(
+            root.join("foo/BUILD.toml")
+                .unwrap()
+                .read_to_string()
+                .unwrap(),
+            "[[library]]\nname = \"a\"\ndependencies = [\":b\"]\n"
+        );
+    }
+}
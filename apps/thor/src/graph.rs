@@ -0,0 +1,364 @@
+use crate::{context::Context, errors::ThorError};
+use build_graph::{
+    expand_target_pattern, find_all_dependents, BuildGraph, DependentsIndex, TargetFiles,
+};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+use target::Target;
+use virtual_io::VirtualIo;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum GraphFormat {
+    Dot,
+    Json,
+    Mermaid,
+}
+
+#[derive(Serialize)]
+struct JsonTarget<'a> {
+    target: String,
+    kind: &'static str,
+    files: &'a [String],
+    dependencies: Vec<String>,
+    /// Every target in the workspace that depends on this one directly.
+    dependents: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct JsonGraph<'a> {
+    targets: Vec<JsonTarget<'a>>,
+}
+
+/// The part of the graph selected for rendering, in topological order.
+struct Subgraph<'a> {
+    nodes: Vec<&'a TargetFiles>,
+    dependencies: Vec<Vec<String>>,
+}
+
+fn is_in_directory(node: &TargetFiles, prefix: &str) -> bool {
+    let prefix = prefix.trim_matches('/');
+    let directories = node.target.get_directories();
+    prefix.is_empty()
+        || directories == prefix
+        || directories
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn select_subgraph<'a>(
+    graph: &'a BuildGraph,
+    heads: &[Target],
+    depth: Option<usize>,
+    prefix: Option<&str>,
+) -> Subgraph<'a> {
+    // Breadth first so each target is reached at its smallest depth.
+    let mut depths: HashMap<String, usize> = HashMap::new();
+    let mut queue = VecDeque::new();
+    for head in heads {
+        if depths.insert(head.to_string(), 0).is_none() {
+            queue.push_back((head, 0));
+        }
+    }
+    while let Some((target, target_depth)) = queue.pop_front() {
+        if depth.is_some_and(|depth| target_depth >= depth) {
+            continue;
+        }
+        for dependency in graph.direct_dependencies(target) {
+            if let Entry::Vacant(entry) = depths.entry(dependency.target.to_string()) {
+                entry.insert(target_depth + 1);
+                queue.push_back((&dependency.target, target_depth + 1));
+            }
+        }
+    }
+
+    let nodes = graph
+        .topological_order()
+        .iter()
+        .filter(|node| depths.contains_key(&node.target.to_string()))
+        .filter(|node| prefix.is_none_or(|prefix| is_in_directory(node, prefix)))
+        .collect::<Vec<&TargetFiles>>();
+    let included = nodes
+        .iter()
+        .map(|node| node.target.to_string())
+        .collect::<HashSet<String>>();
+    let included_names = |neighbors: Vec<&TargetFiles>| {
+        neighbors
+            .iter()
+            .map(|neighbor| neighbor.target.to_string())
+            .filter(|neighbor| included.contains(neighbor))
+            .collect::<Vec<String>>()
+    };
+
+    Subgraph {
+        dependencies: nodes
+            .iter()
+            .map(|node| included_names(graph.direct_dependencies(&node.target)))
+            .collect(),
+        nodes,
+    }
+}
+
+fn render_dot(subgraph: &Subgraph) -> String {
+    let mut output = String::from("digraph dependencies {\n");
+    for (node, dependencies) in subgraph.nodes.iter().zip(&subgraph.dependencies) {
+        output.push_str(&format!("  \"{}\";\n", node.target));
+        for dependency in dependencies {
+            output.push_str(&format!("  \"{}\" -> \"{dependency}\";\n", node.target));
+        }
+    }
+    output.push('}');
+    output
+}
+
+fn render_mermaid(subgraph: &Subgraph) -> String {
+    // Mermaid node ids cannot contain `:` or `/`, so nodes get numeric ids
+    // and use the target as their label.
+    let ids = subgraph
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(index, node)| (node.target.to_string(), format!("n{index}")))
+        .collect::<HashMap<String, String>>();
+    let mut output = String::from("graph TD\n");
+    for node in &subgraph.nodes {
+        let target = node.target.to_string();
+        output.push_str(&format!("  {}[\"{target}\"]\n", ids[&target]));
+    }
+    for (node, dependencies) in subgraph.nodes.iter().zip(&subgraph.dependencies) {
+        for dependency in dependencies {
+            output.push_str(&format!(
+                "  {} --> {}\n",
+                ids[&node.target.to_string()],
+                ids[dependency]
+            ));
+        }
+    }
+    output.trim_end().to_string()
+}
+
+fn render_json(subgraph: &Subgraph, dependents: &DependentsIndex) -> String {
+    let graph = JsonGraph {
+        targets: subgraph
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| JsonTarget {
+                target: node.target.to_string(),
+                kind: node.definition.kind().as_str(),
+                files: &node.files,
+                dependencies: subgraph.dependencies[index].clone(),
+                dependents: dependents
+                    .get(&node.target)
+                    .into_iter()
+                    .flatten()
+                    .map(|dependent| dependent.to_string())
+                    .collect(),
+            })
+            .collect(),
+    };
+    serde_json::to_string_pretty(&graph).unwrap()
+}
+
+/// Prints the dependency graph of the targets matching `pattern`. `depth`
+/// limits how many edges away from those targets to go, and `prefix` only
+/// keeps targets in that directory or below it.
+pub fn do_graph(
+    context: &Context,
+    vio: &mut impl VirtualIo,
+    pattern: &str,
+    format: GraphFormat,
+    depth: Option<usize>,
+    prefix: Option<&str>,
+) -> Result<(), ThorError> {
+    let target = context.parse_label(pattern)?;
+    let root = &context.root;
+    let heads = expand_target_pattern(&target, root).map_err(ThorError::DependencySortError)?;
+    let graph = BuildGraph::resolve(target, root).map_err(ThorError::DependencySortError)?;
+    let subgraph = select_subgraph(&graph, &heads, depth, prefix);
+
+    vio.println(match format {
+        GraphFormat::Dot => render_dot(&subgraph),
+        GraphFormat::Json => render_json(
+            &subgraph,
+            &find_all_dependents(root).map_err(ThorError::DependencySortError)?,
+        ),
+        GraphFormat::Mermaid => render_mermaid(&subgraph),
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::create_file::create_test_file;
+    use vfs::{MemoryFS, VfsPath};
+    use virtual_io::VioFakeBuilder;
+
+    /// app:main -> lib:a -> lib/deep:c
+    fn workspace() -> VfsPath {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "app/BUILD.toml",
+            b"
+            [[binary]]
+            name = \"main\"
+            entry_point = \"main.buri\"
+            files = [\"main.buri\"]
+            dependencies = [\"//lib:a\"]
+            ",
+        );
+        create_test_file(
+            &root,
+            "lib/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//lib/deep:c\"]
+            ",
+        );
+        create_test_file(
+            &root,
+            "lib/deep/BUILD.toml",
+            b"
+            [[library]]
+            name = \"c\"
+            ",
+        );
+        root
+    }
+
+    fn graph(
+        pattern: &str,
+        format: GraphFormat,
+        depth: Option<usize>,
+        prefix: Option<&str>,
+        expected: &str,
+    ) {
+        let mut vio = VioFakeBuilder::new().expect_stdout(expected).build();
+        do_graph(
+            &Context::new(workspace(), ""),
+            &mut vio,
+            pattern,
+            format,
+            depth,
+            prefix,
+        )
+        .unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn renders_dot() {
+        graph(
+            "app:main",
+            GraphFormat::Dot,
+            None,
+            None,
+            "digraph dependencies {
+  \"lib/deep:c\";
+  \"lib:a\";
+  \"lib:a\" -> \"lib/deep:c\";
+  \"app:main\";
+  \"app:main\" -> \"lib:a\";
+}
+",
+        );
+    }
+
+    #[test]
+    fn renders_mermaid() {
+        graph(
+            "app:main",
+            GraphFormat::Mermaid,
+            None,
+            None,
+            "graph TD
+  n0[\"lib/deep:c\"]
+  n1[\"lib:a\"]
+  n2[\"app:main\"]
+  n1 --> n0
+  n2 --> n1
+",
+        );
+    }
+
+    #[test]
+    fn renders_json_with_files_dependencies_and_dependents() {
+        let root = workspace();
+        let target = target::parse::parse_target("app:main").unwrap();
+        let graph = BuildGraph::resolve(target.clone(), &root).unwrap();
+        let subgraph = select_subgraph(&graph, &[target], None, None);
+        let dependents = find_all_dependents(&root).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&render_json(&subgraph, &dependents)).unwrap();
+        assert_eq!(
+            json["targets"][1],
+            serde_json::json!({
+                "target": "lib:a",
+                "kind": "library",
+                "files": [],
+                "dependencies": ["lib/deep:c"],
+                "dependents": ["app:main"],
+            })
+        );
+        assert_eq!(json["targets"][2]["kind"], "binary");
+        assert_eq!(
+            json["targets"][2]["files"],
+            serde_json::json!(["main.buri"])
+        );
+        // Dependents outside the rendered graph are listed too.
+        let target = target::parse::parse_target("lib:a").unwrap();
+        let subgraph = select_subgraph(&graph, &[target], None, None);
+        let lib_json: serde_json::Value =
+            serde_json::from_str(&render_json(&subgraph, &dependents)).unwrap();
+        assert_eq!(lib_json["targets"][1]["target"], "lib:a");
+        assert_eq!(
+            lib_json["targets"][1]["dependents"],
+            serde_json::json!(["app:main"])
+        );
+    }
+
+    #[test]
+    fn limits_the_depth() {
+        graph(
+            "app:main",
+            GraphFormat::Dot,
+            Some(1),
+            None,
+            "digraph dependencies {
+  \"lib:a\";
+  \"app:main\";
+  \"app:main\" -> \"lib:a\";
+}
+",
+        );
+    }
+
+    #[test]
+    fn filters_by_directory_prefix() {
+        graph(
+            "app:main",
+            GraphFormat::Dot,
+            None,
+            Some("lib"),
+            "digraph dependencies {
+  \"lib/deep:c\";
+  \"lib:a\";
+  \"lib:a\" -> \"lib/deep:c\";
+}
+",
+        );
+        graph(
+            "app:main",
+            GraphFormat::Dot,
+            None,
+            Some("lib/deep"),
+            "digraph dependencies {
+  \"lib/deep:c\";
+}
+",
+        );
+    }
+}
@@ -1,14 +1,17 @@
+use crate::errors::ThorError;
 use files::workspace_file::{WorkspaceFile, WORKSPACE_FILE_NAME};
-use vfs::{VfsError, VfsPath};
+use vfs::VfsPath;
 use virtual_io::VirtualIo;
 
 pub fn do_init(
     root: &VfsPath,
     vio: &mut impl VirtualIo,
     name: &Option<String>,
-) -> Result<(), VfsError> {
-    let workspace_file = root.join(WORKSPACE_FILE_NAME)?;
-    if workspace_file.exists()? {
+) -> Result<(), ThorError> {
+    let workspace_file = root
+        .join(WORKSPACE_FILE_NAME)
+        .map_err(ThorError::VfsError)?;
+    if workspace_file.exists().map_err(ThorError::VfsError)? {
         vio.println("Workspace already exists, no need to create a new one.");
         return Ok(());
     }
@@ -16,10 +19,12 @@ pub fn do_init(
     if let Some(name) = name {
         workspace.name = Some(name.to_string());
     }
-    let formatted_workspace_file = toml::to_string_pretty(&workspace).unwrap();
-    root.join(WORKSPACE_FILE_NAME)?
-        .create_file()?
-        .write_all(formatted_workspace_file.as_bytes())?;
+    let formatted_workspace_file = toml::to_string_pretty(&workspace)
+        .map_err(|error| ThorError::SerializationError(WORKSPACE_FILE_NAME.to_string(), error))?;
+    workspace_file
+        .create_file()
+        .and_then(|mut file| Ok(file.write_all(formatted_workspace_file.as_bytes())?))
+        .map_err(ThorError::VfsError)?;
     vio.println(format!("Created .{}", workspace_file.as_str()));
     Ok(())
 }
@@ -1,7 +1,21 @@
 use clap::{Parser, Subcommand};
-use vfs::{PhysicalFS, VfsError, VfsPath};
+use dirs::cache_dir;
+use errors::ThorError;
+use std::path::{Path, PathBuf};
+use vfs::{PhysicalFS, VfsPath};
+use virtual_io::VirtualIo;
 
+mod build;
+mod cache;
+mod check;
+mod context;
+mod errors;
+mod fix;
+mod format;
+mod graph;
 mod init;
+mod query;
+mod test;
 
 #[derive(Parser)]
 // bin_name = "buri" because the user will invoke the CLI by running `buri`,
@@ -19,22 +33,227 @@ enum Commands {
         #[arg(long)]
         name: Option<String>,
     },
+    /// Build targets and all of their dependencies
+    Build {
+        /// Targets to build, e.g. `foo/bar:baz` or `foo/bar:...`. Prefix a pattern
+        /// with `-` to exclude the targets it matches, e.g. `-foo/legacy/...`
+        #[arg(required = true, allow_hyphen_values = true)]
+        targets: Vec<String>,
+        /// Maximum number of targets to build at once. Defaults to the number of CPUs.
+        #[arg(short, long)]
+        jobs: Option<usize>,
+    },
+    /// Build and run test targets, e.g. `buri test foo:...`
+    Test {
+        /// Targets to test, e.g. `foo/bar:baz` or `foo/bar:...`. Prefix a pattern
+        /// with `-` to exclude the targets it matches. Defaults to the whole workspace.
+        #[arg(default_value = "...", allow_hyphen_values = true)]
+        targets: Vec<String>,
+        /// Only run tests whose target contains this text
+        #[arg(long)]
+        filter: Option<String>,
+        /// Which shard of the tests to run, counting from 0
+        #[arg(long, requires = "shard_count")]
+        shard_index: Option<usize>,
+        /// Number of shards the tests are split between
+        #[arg(long, requires = "shard_index")]
+        shard_count: Option<usize>,
+        /// Write a JUnit XML report of the results to this file
+        #[arg(long)]
+        junit_xml: Option<PathBuf>,
+        /// Maximum number of targets to build at once. Defaults to the number of CPUs.
+        #[arg(short, long)]
+        jobs: Option<usize>,
+    },
+    /// Check that the dependency graph resolves, reporting every problem at once
+    Check {
+        /// Target pattern to check, e.g. `foo/bar:baz`. Defaults to the whole workspace.
+        #[arg(default_value = "...")]
+        pattern: String,
+        /// Stop at the first problem
+        #[arg(long)]
+        fail_fast: bool,
+    },
+    /// Update the `dependents` of libraries to match the targets that depend on them
+    Fix {
+        /// Target pattern to fix, e.g. `foo/bar:baz`. Defaults to the whole workspace.
+        #[arg(default_value = "...")]
+        pattern: String,
+    },
+    /// Rewrite every BUILD.toml and WORKSPACE.toml into the canonical layout
+    Fmt {
+        /// List the files that are not formatted instead of rewriting them,
+        /// failing if there are any
+        #[arg(long)]
+        check: bool,
+    },
+    /// Query the dependency graph, e.g. `buri query "rdeps(..., //foo:bar)"`
+    ///
+    /// Supports deps(x), rdeps(universe, x), somepath(from, to),
+    /// allpaths(from, to), kind(library, x) and the set operators
+    /// `+` (union), `-` (difference) and `^` (intersection).
+    /// `unowned()`, or just `unowned`, evaluates to the source files no
+    /// target claims.
+    Query { expression: String },
+    /// Print the dependency graph of a target pattern
+    Graph {
+        /// Target pattern to graph, e.g. `foo/bar:baz` or `foo/bar:...`
+        pattern: String,
+        #[arg(long, value_enum, default_value_t = graph::GraphFormat::Dot)]
+        format: graph::GraphFormat,
+        /// Only include targets at most this many dependencies away from the pattern
+        #[arg(long)]
+        depth: Option<usize>,
+        /// Only include targets in this directory or below it
+        #[arg(long)]
+        prefix: Option<String>,
+    },
 }
 
-fn main() -> Result<(), VfsError> {
+fn main() {
     let cli = Cli::parse();
 
-    let root: VfsPath = PhysicalFS::new(std::env::current_dir().unwrap()).into();
     let mut vio = virtual_io::Vio::new();
+    let context = context::Context::from_environment().unwrap_or_else(|error| {
+        vio.println(error.to_string());
+        std::process::exit(1);
+    });
 
-    match &cli.command {
-        Some(Commands::Init { name }) => init::do_init(&root, &mut vio, name),
+    let result = match &cli.command {
+        Some(Commands::Init { name }) => init::do_init(&context.root, &mut vio, name),
+        Some(Commands::Build { targets, jobs }) => action_cache().and_then(|cache| {
+            build::do_build(
+                &context,
+                &cache,
+                &mut vio,
+                targets,
+                jobs.unwrap_or_else(build_graph::default_jobs),
+            )
+        }),
+        Some(Commands::Test {
+            targets,
+            filter,
+            shard_index,
+            shard_count,
+            junit_xml,
+            jobs,
+        }) => action_cache().and_then(|cache| {
+            let junit_xml = junit_xml.as_deref().map(report_path).transpose()?;
+            let options = test::TestOptions {
+                filter: filter.clone(),
+                shard: match (shard_index, shard_count) {
+                    (Some(index), Some(count)) => test::Shard {
+                        index: *index,
+                        count: *count,
+                    },
+                    _ => test::Shard::ALL,
+                },
+                junit_xml,
+                jobs: jobs.unwrap_or_else(build_graph::default_jobs),
+            };
+            test::do_test(&context, &cache, &mut vio, targets, &options)
+        }),
+        Some(Commands::Check { pattern, fail_fast }) => {
+            check::do_check(&context, &mut vio, pattern, *fail_fast)
+        }
+        Some(Commands::Fix { pattern }) => fix::do_fix(&context, &mut vio, pattern),
+        Some(Commands::Fmt { check }) => format::do_fmt(&context, &mut vio, *check),
+        Some(Commands::Query { expression }) => query::do_query(&context, &mut vio, expression),
+        Some(Commands::Graph {
+            pattern,
+            format,
+            depth,
+            prefix,
+        }) => graph::do_graph(
+            &context,
+            &mut vio,
+            pattern,
+            *format,
+            *depth,
+            prefix.as_deref(),
+        ),
         None => Ok(()),
+    };
+
+    if let Err(error) = result {
+        vio.println(error.to_string());
+        std::process::exit(1);
     }
 }
 
+/// The file at `path`, relative to the current directory, to write a report to.
+fn report_path(path: &Path) -> Result<VfsPath, ThorError> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| ThorError::InvalidReportPath(path.display().to_string()))?;
+    let directory = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    VfsPath::from(PhysicalFS::new(directory))
+        .join(file_name.to_string_lossy())
+        .map_err(ThorError::VfsError)
+}
+
+fn action_cache() -> Result<cache::ActionCache, ThorError> {
+    let user_cache_dir: VfsPath =
+        PhysicalFS::new(cache_dir().ok_or(ThorError::NoCacheDirectory)?).into();
+    user_cache_dir
+        .join("buri")
+        .and_then(|cache_dir| cache::ActionCache::new(&cache_dir))
+        .map_err(ThorError::VfsError)
+}
+
+#[test]
+fn rejects_report_paths_that_are_not_files() {
+    for path in ["..", "reports/..", "/"] {
+        assert!(
+            matches!(
+                report_path(Path::new(path)),
+                Err(ThorError::InvalidReportPath(_))
+            ),
+            "{path}"
+        );
+    }
+    assert_eq!(
+        report_path(Path::new("reports/junit.xml"))
+            .unwrap()
+            .filename(),
+        "junit.xml"
+    );
+}
+
 #[test]
 fn verify_cli() {
     use clap::CommandFactory;
     Cli::command().debug_assert()
 }
+
+#[test]
+fn accepts_exclusion_patterns() {
+    let cli =
+        Cli::try_parse_from(["buri", "build", "-j", "2", "foo:...", "-foo/legacy:..."]).unwrap();
+    let Some(Commands::Build { targets, jobs }) = cli.command else {
+        panic!("expected a build command");
+    };
+    assert_eq!(targets, Vec::from(["foo:...", "-foo/legacy:..."]));
+    assert_eq!(jobs, Some(2));
+}
+
+#[test]
+fn shard_flags_must_be_given_together() {
+    assert!(Cli::try_parse_from(["buri", "test", "--shard-index", "0"]).is_err());
+    let cli =
+        Cli::try_parse_from(["buri", "test", "--shard-index", "1", "--shard-count", "3"]).unwrap();
+    let Some(Commands::Test {
+        targets,
+        shard_index,
+        shard_count,
+        ..
+    }) = cli.command
+    else {
+        panic!("expected a test command");
+    };
+    assert_eq!(targets, Vec::from(["..."]));
+    assert_eq!((shard_index, shard_count), (Some(1), Some(3)));
+}
@@ -0,0 +1,74 @@
+use crate::{context::Context, errors::ThorError};
+use query::evaluate_query;
+use virtual_io::VirtualIo;
+
+/// Prints the targets `query` evaluates to. Labels in the query are relative
+/// to the working directory's package.
+pub fn do_query(context: &Context, vio: &mut impl VirtualIo, query: &str) -> Result<(), ThorError> {
+    let items =
+        evaluate_query(query, &context.root, &context.package).map_err(ThorError::QueryError)?;
+    for item in items {
+        vio.println(item.to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::create_file::create_test_file;
+    use vfs::{MemoryFS, VfsPath};
+
+    #[test]
+    fn prints_one_target_per_line() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//foo:b\"]
+
+            [[library]]
+            name = \"b\"
+            ",
+        );
+        let mut vio = virtual_io::VioFakeBuilder::new()
+            .expect_stdout("foo:a\n")
+            .expect_stdout("foo:b\n")
+            .build();
+        do_query(&Context::new(root, ""), &mut vio, "deps(//foo:a)").unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn lists_unowned_source_files() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            files = [\"a.buri\"]
+            ",
+        );
+        create_test_file(&root, "foo/a.buri", b"");
+        create_test_file(&root, "foo/b.buri", b"");
+        create_test_file(&root, "foo/README.md", b"");
+        let mut vio = virtual_io::VioFakeBuilder::new()
+            .expect_stdout("foo/b.buri\n")
+            .build();
+        do_query(&Context::new(root, ""), &mut vio, "unowned()").unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn errors_on_invalid_query() {
+        let root: VfsPath = MemoryFS::new().into();
+        let mut vio = virtual_io::VioFakeBuilder::new().build();
+        let result = do_query(&Context::new(root, ""), &mut vio, "deps(");
+        assert!(matches!(result, Err(ThorError::QueryError(_))));
+    }
+}
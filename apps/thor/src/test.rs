@@ -0,0 +1,449 @@
+use crate::{
+    build::{describe_build_outcome, parse_patterns, run_build_action, BuildResult},
+    cache::ActionCache,
+    context::Context,
+    errors::ThorError,
+};
+use build_graph::{
+    execute_in_parallel, topologically_sort_dep_graphs, BuildGraph, TargetFiles, TargetOutcome,
+};
+use files::build_file::TargetKind;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    sync::Mutex,
+};
+use target::Target;
+use vfs::VfsPath;
+use virtual_io::VirtualIo;
+
+/// Splits the tests between `count` machines, of which this is number `index`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Shard {
+    pub const ALL: Shard = Shard { index: 0, count: 1 };
+}
+
+pub struct TestOptions {
+    /// Only run tests whose target contains this text
+    pub filter: Option<String>,
+    pub shard: Shard,
+    /// Where to write a JUnit XML report, if anywhere
+    pub junit_xml: Option<VfsPath>,
+    pub jobs: usize,
+}
+
+const NO_TEST_RUNNER: &str = "there is no test runner yet";
+
+#[derive(Debug, PartialEq)]
+enum TestStatus {
+    /// Built, but there is no test runner to run it with yet
+    NotRun,
+    Failed(String),
+    /// A dependency failed to build
+    Skipped,
+}
+
+/// Selects the tests matched by `patterns`, `options.filter` and
+/// `options.shard`, builds their dependencies in topological order and runs
+/// them in parallel. Results are cached by the same digest as builds, so a
+/// test whose inputs have not changed is not run again. There is no test
+/// runner yet, so for now tests are only built and reported as not run.
+pub fn do_test(
+    context: &Context,
+    cache: &ActionCache,
+    vio: &mut impl VirtualIo,
+    patterns: &[String],
+    options: &TestOptions,
+) -> Result<(), ThorError> {
+    let Shard { index, count } = options.shard;
+    if index >= count {
+        return Err(ThorError::InvalidShard(index, count));
+    }
+    let (included, excluded) = parse_patterns(context, patterns)?;
+    let root = &context.root;
+    let sorted = topologically_sort_dep_graphs(&included, &excluded, root)
+        .map_err(ThorError::DependencySortError)?;
+
+    let mut tests = sorted
+        .iter()
+        .filter(|target_files| target_files.definition.kind() == TargetKind::Test)
+        .map(|target_files| target_files.target.clone())
+        .filter(|target| {
+            options
+                .filter
+                .as_ref()
+                .is_none_or(|filter| target.to_string().contains(filter.as_str()))
+        })
+        .collect::<Vec<Target>>();
+    // Sorting first gives every shard the same view of which tests go where.
+    tests.sort();
+    let tests = tests
+        .into_iter()
+        .enumerate()
+        .filter(|(position, _)| position % count == index)
+        .map(|(_, target)| target)
+        .collect::<HashSet<Target>>();
+    if tests.is_empty() {
+        vio.println("No tests found");
+        return Ok(());
+    }
+
+    let graph = BuildGraph::new(select_with_dependencies(sorted, &tests));
+    let digests = Mutex::new(HashMap::new());
+    let mut results = Vec::new();
+    execute_in_parallel(
+        &graph,
+        options.jobs,
+        |target_files| run_build_action(root, cache, &digests, target_files),
+        |target_files, outcome| {
+            if !tests.contains(&target_files.target) {
+                vio.println(describe_build_outcome(target_files, outcome));
+                return;
+            }
+            let target = &target_files.target;
+            let status = match outcome {
+                TargetOutcome::Succeeded(BuildResult::Built) => {
+                    vio.println(format!("BUILT {target} (not run, {NO_TEST_RUNNER})"));
+                    TestStatus::NotRun
+                }
+                TargetOutcome::Succeeded(BuildResult::UpToDate) => {
+                    vio.println(format!(
+                        "BUILT {target} (cached, not run, {NO_TEST_RUNNER})"
+                    ));
+                    TestStatus::NotRun
+                }
+                TargetOutcome::Failed(error) => {
+                    vio.println(format!("FAIL {target}: {error}"));
+                    TestStatus::Failed(error.to_string())
+                }
+                TargetOutcome::Panicked(message) => {
+                    let message = format!("test action panicked: {message}");
+                    vio.println(format!("FAIL {target}: {message}"));
+                    TestStatus::Failed(message)
+                }
+                TargetOutcome::Skipped => {
+                    vio.println(format!(
+                        "SKIP {target} because a dependency failed to build"
+                    ));
+                    TestStatus::Skipped
+                }
+            };
+            results.push((target.clone(), status));
+        },
+    );
+    results.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let count_of = |wanted: fn(&TestStatus) -> bool| {
+        results.iter().filter(|(_, status)| wanted(status)).count()
+    };
+    let not_run = count_of(|status| *status == TestStatus::NotRun);
+    let failed = count_of(|status| matches!(status, TestStatus::Failed(_)));
+    let skipped = count_of(|status| *status == TestStatus::Skipped);
+    vio.println(format!(
+        "Tests: {not_run} not run, {failed} failed, {skipped} skipped"
+    ));
+
+    if let Some(path) = &options.junit_xml {
+        path.create_file()
+            .and_then(|mut file| Ok(file.write_all(junit_xml(&results).as_bytes())?))
+            .map_err(ThorError::VfsError)?;
+    }
+    if failed > 0 || skipped > 0 {
+        return Err(ThorError::TestsFailed);
+    }
+    Ok(())
+}
+
+/// Keeps the targets in `tests` and everything they transitively depend on,
+/// preserving the topological order of `sorted`.
+fn select_with_dependencies(sorted: Vec<TargetFiles>, tests: &HashSet<Target>) -> Vec<TargetFiles> {
+    let mut needed = tests.clone();
+    // Dependents come after their dependencies, so walking backwards sees every
+    // dependent before deciding whether its dependencies are needed.
+    for target_files in sorted.iter().rev() {
+        if needed.contains(&target_files.target) {
+            needed.extend(target_files.dependencies.iter().cloned());
+        }
+    }
+    sorted
+        .into_iter()
+        .filter(|target_files| needed.contains(&target_files.target))
+        .collect()
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn junit_xml(results: &[(Target, TestStatus)]) -> String {
+    let count_of = |wanted: fn(&TestStatus) -> bool| {
+        results.iter().filter(|(_, status)| wanted(status)).count()
+    };
+    let failures = count_of(|status| matches!(status, TestStatus::Failed(_)));
+    // Tests that were not run are skipped as far as JUnit is concerned.
+    let skipped = count_of(|status| matches!(status, TestStatus::NotRun | TestStatus::Skipped));
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <testsuites tests=\"{0}\" failures=\"{failures}\" skipped=\"{skipped}\">\n  \
+         <testsuite name=\"buri\" tests=\"{0}\" failures=\"{failures}\" skipped=\"{skipped}\">\n",
+        results.len()
+    );
+    for (target, status) in results {
+        let name = escape_xml(&target.to_string());
+        let classname = escape_xml(target.get_directories());
+        match status {
+            TestStatus::NotRun => xml.push_str(&format!(
+                "    <testcase name=\"{name}\" classname=\"{classname}\">\n      \
+                 <skipped message=\"{NO_TEST_RUNNER}\"/>\n    </testcase>\n"
+            )),
+            TestStatus::Failed(message) => xml.push_str(&format!(
+                "    <testcase name=\"{name}\" classname=\"{classname}\">\n      \
+                 <failure message=\"{}\"/>\n    </testcase>\n",
+                escape_xml(message)
+            )),
+            TestStatus::Skipped => xml.push_str(&format!(
+                "    <testcase name=\"{name}\" classname=\"{classname}\">\n      \
+                 <skipped message=\"a dependency failed to build\"/>\n    </testcase>\n"
+            )),
+        }
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use target::parse::parse_target;
+    use test_utils::create_file::create_test_file;
+    use vfs::MemoryFS;
+    use virtual_io::VioFakeBuilder;
+
+    fn workspace() -> VfsPath {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "app/BUILD.toml",
+            b"
+            [[library]]
+            name = \"lib\"
+            files = [\"lib.buri\"]
+
+            [[binary]]
+            name = \"main\"
+            entry_point = \"main.buri\"
+            files = [\"main.buri\"]
+            dependencies = [\":lib\"]
+
+            [[test]]
+            name = \"lib_test\"
+            files = [\"lib_test.buri\"]
+            dependencies = [\":lib\"]
+
+            [[test]]
+            name = \"main_test\"
+            files = [\"main_test.buri\"]
+            dependencies = [\":main\"]
+            ",
+        );
+        create_test_file(&root, "app/lib.buri", b"lib");
+        create_test_file(&root, "app/main.buri", b"main");
+        create_test_file(&root, "app/lib_test.buri", b"lib_test");
+        create_test_file(&root, "app/main_test.buri", b"main_test");
+        root
+    }
+
+    fn options() -> TestOptions {
+        TestOptions {
+            filter: None,
+            shard: Shard::ALL,
+            junit_xml: None,
+            // A single job keeps the output order deterministic.
+            jobs: 1,
+        }
+    }
+
+    fn test_with_cache(
+        root: &VfsPath,
+        cache_dir: &VfsPath,
+        vio: &mut impl VirtualIo,
+        patterns: &[&str],
+        options: &TestOptions,
+    ) -> Result<(), ThorError> {
+        let cache = ActionCache::new(cache_dir).unwrap();
+        let patterns = patterns
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .collect::<Vec<String>>();
+        do_test(
+            &Context::new(root.clone(), ""),
+            &cache,
+            vio,
+            &patterns,
+            options,
+        )
+    }
+
+    fn run(
+        root: &VfsPath,
+        vio: &mut impl VirtualIo,
+        patterns: &[&str],
+        options: &TestOptions,
+    ) -> Result<(), ThorError> {
+        test_with_cache(root, &MemoryFS::new().into(), vio, patterns, options)
+    }
+
+    #[test]
+    fn builds_dependencies_and_runs_tests() {
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("Built app:lib\n")
+            .expect_stdout("Built app:main\n")
+            .expect_stdout("BUILT app:lib_test (not run, there is no test runner yet)\n")
+            .expect_stdout("BUILT app:main_test (not run, there is no test runner yet)\n")
+            .expect_stdout("Tests: 2 not run, 0 failed, 0 skipped\n")
+            .build();
+        run(&workspace(), &mut vio, &["..."], &options()).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn caches_test_results() {
+        let root = workspace();
+        let cache_dir: VfsPath = MemoryFS::new().into();
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("Built app:lib\n")
+            .expect_stdout("BUILT app:lib_test (not run, there is no test runner yet)\n")
+            .expect_stdout("Tests: 1 not run, 0 failed, 0 skipped\n")
+            .build();
+        test_with_cache(&root, &cache_dir, &mut vio, &["app:lib_test"], &options()).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("app:lib is up to date\n")
+            .expect_stdout("BUILT app:lib_test (cached, not run, there is no test runner yet)\n")
+            .expect_stdout("Tests: 1 not run, 0 failed, 0 skipped\n")
+            .build();
+        test_with_cache(&root, &cache_dir, &mut vio, &["app:lib_test"], &options()).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn filters_tests_by_name() {
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("Built app:lib\n")
+            .expect_stdout("Built app:main\n")
+            .expect_stdout("BUILT app:main_test (not run, there is no test runner yet)\n")
+            .expect_stdout("Tests: 1 not run, 0 failed, 0 skipped\n")
+            .build();
+        let options = TestOptions {
+            filter: Some("main".to_string()),
+            ..options()
+        };
+        run(&workspace(), &mut vio, &["..."], &options).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn shards_split_the_tests_between_them() {
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("Built app:lib\n")
+            .expect_stdout("BUILT app:lib_test (not run, there is no test runner yet)\n")
+            .expect_stdout("Tests: 1 not run, 0 failed, 0 skipped\n")
+            .build();
+        let first = TestOptions {
+            shard: Shard { index: 0, count: 2 },
+            ..options()
+        };
+        run(&workspace(), &mut vio, &["..."], &first).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("Built app:lib\n")
+            .expect_stdout("Built app:main\n")
+            .expect_stdout("BUILT app:main_test (not run, there is no test runner yet)\n")
+            .expect_stdout("Tests: 1 not run, 0 failed, 0 skipped\n")
+            .build();
+        let second = TestOptions {
+            shard: Shard { index: 1, count: 2 },
+            ..options()
+        };
+        run(&workspace(), &mut vio, &["..."], &second).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn rejects_shards_out_of_range() {
+        let mut vio = VioFakeBuilder::new().build();
+        let options = TestOptions {
+            shard: Shard { index: 2, count: 2 },
+            ..options()
+        };
+        let result = run(&workspace(), &mut vio, &["..."], &options);
+        assert!(matches!(result, Err(ThorError::InvalidShard(2, 2))));
+    }
+
+    #[test]
+    fn reports_when_no_tests_match() {
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("No tests found\n")
+            .build();
+        run(&workspace(), &mut vio, &["app:lib"], &options()).unwrap();
+        assert_eq!(vio.get_actual(), vio.get_expected());
+    }
+
+    #[test]
+    fn writes_a_junit_report() {
+        let root = workspace();
+        root.join("app/main.buri").unwrap().remove_file().unwrap();
+        let report: VfsPath = MemoryFS::new().into();
+        let report = report.join("report.xml").unwrap();
+        let mut vio = VioFakeBuilder::new()
+            .expect_stdout("Built app:lib\n")
+            .expect_stdout("Failed to build app:main: source file app/main.buri does not exist\n")
+            .expect_stdout("SKIP app:main_test because a dependency failed to build\n")
+            .expect_stdout("BUILT app:lib_test (not run, there is no test runner yet)\n")
+            .expect_stdout("Tests: 1 not run, 0 failed, 1 skipped\n")
+            .build();
+        let options = TestOptions {
+            junit_xml: Some(report.clone()),
+            ..options()
+        };
+        let result = run(&root, &mut vio, &["..."], &options);
+        assert!(matches!(result, Err(ThorError::TestsFailed)));
+        assert_eq!(vio.get_actual(), vio.get_expected());
+        assert_eq!(
+            report.read_to_string().unwrap(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<testsuites tests=\"2\" failures=\"0\" skipped=\"2\">
+  <testsuite name=\"buri\" tests=\"2\" failures=\"0\" skipped=\"2\">
+    <testcase name=\"app:lib_test\" classname=\"app\">
+      <skipped message=\"there is no test runner yet\"/>
+    </testcase>
+    <testcase name=\"app:main_test\" classname=\"app\">
+      <skipped message=\"a dependency failed to build\"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"
+        );
+    }
+
+    #[test]
+    fn escapes_failure_messages() {
+        let results = [(
+            parse_target("app:t").unwrap(),
+            TestStatus::Failed("expected <a> & \"b\"".to_string()),
+        )];
+        assert!(junit_xml(&results)
+            .contains("<failure message=\"expected &lt;a&gt; &amp; &quot;b&quot;\"/>"));
+    }
+}
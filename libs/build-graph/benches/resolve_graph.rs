@@ -0,0 +1,84 @@
+use build_graph::topologically_sort_dep_graph;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use files::{
+    build_file::{build_file_location, BuildFile},
+    diagnostic::parse_toml,
+};
+use std::collections::HashSet;
+use target::{parse::parse_target, Target};
+use test_utils::create_file::create_test_file;
+use vfs::{MemoryFS, VfsPath};
+
+const PACKAGES: usize = 100;
+const LIBRARIES_PER_PACKAGE: usize = 100;
+
+/// A workspace of 10,000 libraries. Every library depends on the next library
+/// in its package and on the library with the same name in the next package,
+/// so most build files are reached from many targets.
+fn synthetic_workspace() -> VfsPath {
+    let root: VfsPath = MemoryFS::new().into();
+    for package in 0..PACKAGES {
+        let mut build_file = String::new();
+        for library in 0..LIBRARIES_PER_PACKAGE {
+            let mut dependencies = Vec::new();
+            if library + 1 < LIBRARIES_PER_PACKAGE {
+                dependencies.push(format!("\":lib{}\"", library + 1));
+            }
+            if package + 1 < PACKAGES {
+                dependencies.push(format!("\"//pkg{}:lib{library}\"", package + 1));
+            }
+            build_file.push_str(&format!(
+                "[[library]]\nname = \"lib{library}\"\nfiles = [\"lib{library}.buri\"]\ndependencies = [{}]\n\n",
+                dependencies.join(", ")
+            ));
+        }
+        create_test_file(
+            &root,
+            &format!("pkg{package}/BUILD.toml"),
+            build_file.as_bytes(),
+        );
+    }
+    root
+}
+
+/// The baseline the package cache is measured against: every target looked up
+/// reads and parses its build file again. Returns how many targets were
+/// reached.
+fn resolve_without_cache(head: Target, root: &VfsPath) -> usize {
+    let mut visited = HashSet::new();
+    let mut stack = Vec::from([head]);
+    while let Some(target) = stack.pop() {
+        if !visited.insert(target.to_string()) {
+            continue;
+        }
+        let path = build_file_location(&target);
+        let contents = root.join(&path).unwrap().read_to_string().unwrap();
+        let build_file = parse_toml::<BuildFile>(&path, &contents).unwrap();
+        let definition = build_file.target(target.name()).unwrap();
+        for dependency in definition.dependencies() {
+            stack.push(dependency.in_package(target.get_directories()));
+        }
+    }
+    visited.len()
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let root = synthetic_workspace();
+    let mut group = c.benchmark_group("resolve 10k targets");
+    group.sample_size(10);
+    group.bench_function("one head target", |b| {
+        b.iter(|| {
+            topologically_sort_dep_graph(black_box(parse_target("pkg0:lib0").unwrap()), &root)
+        })
+    });
+    group.bench_function("one head target without the package cache", |b| {
+        b.iter(|| resolve_without_cache(black_box(parse_target("pkg0:lib0").unwrap()), &root))
+    });
+    group.bench_function("recursive pattern", |b| {
+        b.iter(|| topologically_sort_dep_graph(black_box(parse_target("...").unwrap()), &root))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
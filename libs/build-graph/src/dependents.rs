@@ -0,0 +1,256 @@
+use crate::{
+    package_cache::PackageCache,
+    topological_sort::{expand_pattern, find_packages, DependencySortError},
+};
+use files::build_file::{build_file_location, TargetDefinition};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+use target::{parse::parse_target, Target};
+use vfs::VfsPath;
+
+/// A library whose `dependents` disagree with the targets that actually
+/// depend on it.
+#[derive(Debug, PartialEq)]
+pub struct DependentsMismatch {
+    pub library: Target,
+    /// Targets that depend on the library but are not listed
+    pub missing: Vec<Target>,
+    /// Listed targets that do not depend on the library
+    pub stale: Vec<Target>,
+    /// Every target that depends on the library, in order
+    pub actual: Vec<Target>,
+}
+
+fn join(targets: &[Target]) -> String {
+    targets
+        .iter()
+        .map(|target| target.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl fmt::Display for DependentsMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Dependents of {} in {} do not match the targets that depend on it",
+            self.library,
+            self.file()
+        )?;
+        if !self.missing.is_empty() {
+            write!(f, "\n  missing: {}", join(&self.missing))?;
+        }
+        if !self.stale.is_empty() {
+            write!(f, "\n  not a dependent: {}", join(&self.stale))?;
+        }
+        write!(f, "\n  help: run `buri fix` to update them")
+    }
+}
+
+impl DependentsMismatch {
+    /// The build file the library is defined in.
+    pub fn file(&self) -> String {
+        build_file_location(&self.library)
+    }
+}
+
+/// Direct dependents of every target in the workspace.
+pub type DependentsIndex = BTreeMap<Target, BTreeSet<Target>>;
+
+/// Every target defined in the workspace with its definition, and the
+/// workspace-wide index of which targets depend on each of them.
+///
+/// Build files that do not parse and dependencies that do not resolve are
+/// ignored here, as resolving the dependency graph reports them.
+fn index_workspace(
+    root: &VfsPath,
+) -> Result<(Vec<(Target, TargetDefinition)>, DependentsIndex), DependencySortError> {
+    let mut packages = PackageCache::new();
+    let everything = parse_target("...").unwrap();
+    let mut definitions = Vec::new();
+    for (package, build_file_path) in find_packages(&everything, root)? {
+        let Ok(build_file) = packages.package_at(&package, &build_file_path) else {
+            continue;
+        };
+        for name in build_file.target_names() {
+            let target = parse_target(&format!("{package}:{name}"))
+                .map_err(DependencySortError::ParseTargetError)?;
+            if let Some(definition) = build_file.target(name) {
+                definitions.push((target, definition));
+            }
+        }
+    }
+
+    let mut dependents = DependentsIndex::new();
+    for (target, definition) in &definitions {
+        for dependency in definition.dependencies() {
+            let dependency = dependency.in_package(target.get_directories());
+            let Ok(dependencies) = expand_pattern(&dependency, root, &mut packages) else {
+                continue;
+            };
+            for dependency in dependencies {
+                if dependency != *target {
+                    dependents
+                        .entry(dependency)
+                        .or_default()
+                        .insert(target.clone());
+                }
+            }
+        }
+    }
+    Ok((definitions, dependents))
+}
+
+/// Finds the targets in the workspace that depend directly on each target,
+/// so tools do not have to read every build file themselves.
+pub fn find_all_dependents(root: &VfsPath) -> Result<DependentsIndex, DependencySortError> {
+    Ok(index_workspace(root)?.1)
+}
+
+/// Compares the `dependents` of every library matched by `pattern` with the
+/// targets in the workspace that depend on it. Libraries without a
+/// `dependents` field are not checked.
+pub fn find_dependents_mismatches(
+    pattern: &Target,
+    root: &VfsPath,
+) -> Result<Vec<DependentsMismatch>, DependencySortError> {
+    let (definitions, mut dependents) = index_workspace(root)?;
+    let mut mismatches = Vec::new();
+    for (target, definition) in definitions {
+        let TargetDefinition::Library(library) = definition else {
+            continue;
+        };
+        let Some(declared) = &library.dependents else {
+            continue;
+        };
+        if !pattern.matches(&target) {
+            continue;
+        }
+        let declared = declared
+            .iter()
+            .map(|dependent| dependent.in_package(target.get_directories()))
+            .collect::<BTreeSet<Target>>();
+        let actual = dependents.remove(&target).unwrap_or_default();
+        if declared == actual {
+            continue;
+        }
+        mismatches.push(DependentsMismatch {
+            missing: actual.difference(&declared).cloned().collect(),
+            stale: declared.difference(&actual).cloned().collect(),
+            actual: actual.into_iter().collect(),
+            library: target,
+        });
+    }
+    mismatches.sort_by(|a, b| a.library.cmp(&b.library));
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::create_file::create_test_file;
+    use vfs::MemoryFS;
+
+    fn workspace() -> VfsPath {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "lib/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependents = [\"//app:main\", \"//old:gone\"]
+
+            [[library]]
+            name = \"b\"
+            dependents = [\":a\"]
+
+            [[library]]
+            name = \"unmaintained\"
+            ",
+        );
+        create_test_file(
+            &root,
+            "app/BUILD.toml",
+            b"
+            [[binary]]
+            name = \"main\"
+            entry_point = \"main.buri\"
+            files = [\"main.buri\"]
+            dependencies = [\"//lib:a\", \"//lib:unmaintained\"]
+
+            [[test]]
+            name = \"main_test\"
+            files = [\"main_test.buri\"]
+            dependencies = [\"//lib:...\"]
+            ",
+        );
+        root
+    }
+
+    #[test]
+    fn reports_missing_and_stale_dependents() {
+        let mismatches =
+            find_dependents_mismatches(&parse_target("...").unwrap(), &workspace()).unwrap();
+        let targets = |targets: &[&str]| {
+            targets
+                .iter()
+                .map(|target| parse_target(target).unwrap())
+                .collect::<Vec<Target>>()
+        };
+        assert_eq!(
+            mismatches,
+            Vec::from([
+                DependentsMismatch {
+                    library: parse_target("lib:a").unwrap(),
+                    missing: targets(&["app:main_test"]),
+                    stale: targets(&["old:gone"]),
+                    actual: targets(&["app:main", "app:main_test"]),
+                },
+                DependentsMismatch {
+                    library: parse_target("lib:b").unwrap(),
+                    missing: targets(&["app:main_test"]),
+                    stale: targets(&["lib:a"]),
+                    actual: targets(&["app:main_test"]),
+                },
+            ])
+        );
+        assert_eq!(
+            mismatches[0].to_string(),
+            "Dependents of lib:a in lib/BUILD.toml do not match the targets that depend on it
+  missing: app:main_test
+  not a dependent: old:gone
+  help: run `buri fix` to update them"
+        );
+    }
+
+    #[test]
+    fn ignores_unrelated_broken_build_files() {
+        let root = workspace();
+        create_test_file(&root, "broken/BUILD.toml", b"[[library]]\nname = 1\n");
+        let mismatches =
+            find_dependents_mismatches(&parse_target("lib:b").unwrap(), &root).unwrap();
+        assert_eq!(
+            mismatches
+                .iter()
+                .map(|mismatch| mismatch.library.to_string())
+                .collect::<Vec<String>>(),
+            Vec::from(["lib:b"])
+        );
+    }
+
+    #[test]
+    fn only_checks_libraries_matching_the_pattern() {
+        let mismatches =
+            find_dependents_mismatches(&parse_target("lib:b").unwrap(), &workspace()).unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].library, parse_target("lib:b").unwrap());
+        assert!(
+            find_dependents_mismatches(&parse_target("app:...").unwrap(), &workspace())
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
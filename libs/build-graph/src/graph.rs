@@ -0,0 +1,300 @@
+use crate::{
+    target_files::TargetFiles,
+    topological_sort::{topologically_sort_dep_graph, DependencySortError},
+};
+use std::collections::{hash_map::Entry, HashMap};
+use target::Target;
+use vfs::VfsPath;
+
+/// The resolved dependency graph of one or more targets.
+///
+/// Nodes are stored in topological order, so every node comes after all of
+/// its dependencies. Edges are kept in both directions so dependents can be
+/// found as cheaply as dependencies.
+#[derive(Debug)]
+pub struct BuildGraph {
+    nodes: Vec<TargetFiles>,
+    indices: HashMap<Target, usize>,
+    dependencies: Vec<Vec<usize>>,
+    dependents: Vec<Vec<usize>>,
+}
+
+impl BuildGraph {
+    /// Builds a graph from nodes that are already topologically sorted, such
+    /// as the output of `topologically_sort_dep_graph`. Duplicate targets,
+    /// however they are spelled, are only kept the first time they appear.
+    pub fn new(sorted_nodes: Vec<TargetFiles>) -> Self {
+        let mut nodes = Vec::new();
+        let mut indices = HashMap::new();
+        for node in sorted_nodes {
+            if let Entry::Vacant(entry) = indices.entry(node.target.clone()) {
+                entry.insert(nodes.len());
+                nodes.push(node);
+            }
+        }
+
+        let mut dependencies = vec![Vec::new(); nodes.len()];
+        let mut dependents = vec![Vec::new(); nodes.len()];
+        for (index, node) in nodes.iter().enumerate() {
+            for dependency in &node.dependencies {
+                if let Some(dependency_index) = indices.get(dependency) {
+                    dependencies[index].push(*dependency_index);
+                    dependents[*dependency_index].push(index);
+                }
+            }
+        }
+
+        Self {
+            nodes,
+            indices,
+            dependencies,
+            dependents,
+        }
+    }
+
+    /// Resolves the graph of `head_target` and everything it depends on.
+    pub fn resolve(head_target: Target, root: &VfsPath) -> Result<Self, DependencySortError> {
+        Ok(Self::new(topologically_sort_dep_graph(head_target, root)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn contains(&self, target: &Target) -> bool {
+        self.indices.contains_key(target)
+    }
+
+    pub fn get(&self, target: &Target) -> Option<&TargetFiles> {
+        self.index_of(target).map(|index| &self.nodes[index])
+    }
+
+    /// Every node, with dependencies always before their dependents.
+    pub fn topological_order(&self) -> &[TargetFiles] {
+        &self.nodes
+    }
+
+    pub fn direct_dependencies(&self, target: &Target) -> Vec<&TargetFiles> {
+        self.neighbors(target, &self.dependencies)
+    }
+
+    /// Everything `target` depends on directly or indirectly, in topological
+    /// order. Does not include `target` itself.
+    pub fn transitive_dependencies(&self, target: &Target) -> Vec<&TargetFiles> {
+        self.reachable(target, &self.dependencies)
+    }
+
+    pub fn direct_dependents(&self, target: &Target) -> Vec<&TargetFiles> {
+        self.neighbors(target, &self.dependents)
+    }
+
+    /// Everything that depends on `target` directly or indirectly, in
+    /// topological order. Does not include `target` itself.
+    pub fn transitive_dependents(&self, target: &Target) -> Vec<&TargetFiles> {
+        self.reachable(target, &self.dependents)
+    }
+
+    pub(crate) fn index_of(&self, target: &Target) -> Option<usize> {
+        self.indices.get(target).copied()
+    }
+
+    pub(crate) fn dependency_indices(&self, index: usize) -> &[usize] {
+        &self.dependencies[index]
+    }
+
+    pub(crate) fn dependent_indices(&self, index: usize) -> &[usize] {
+        &self.dependents[index]
+    }
+
+    fn neighbors(&self, target: &Target, edges: &[Vec<usize>]) -> Vec<&TargetFiles> {
+        match self.index_of(target) {
+            Some(index) => edges[index]
+                .iter()
+                .map(|neighbor| &self.nodes[*neighbor])
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn reachable(&self, target: &Target, edges: &[Vec<usize>]) -> Vec<&TargetFiles> {
+        let Some(start) = self.index_of(target) else {
+            return Vec::new();
+        };
+        let mut seen = vec![false; self.nodes.len()];
+        let mut stack = edges[start].clone();
+        while let Some(index) = stack.pop() {
+            if !seen[index] {
+                seen[index] = true;
+                stack.extend(edges[index].iter());
+            }
+        }
+        // Node indices are already in topological order.
+        seen.iter()
+            .enumerate()
+            .filter(|(_, seen)| **seen)
+            .map(|(index, _)| &self.nodes[index])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use target::parse::parse_target;
+    use test_utils::create_file::create_test_file;
+    use vfs::MemoryFS;
+
+    fn names(nodes: Vec<&TargetFiles>) -> Vec<String> {
+        nodes
+            .iter()
+            .map(|node| node.target.to_string())
+            .collect::<Vec<String>>()
+    }
+
+    fn diamond() -> BuildGraph {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//foo:b\", \"//foo:c\"]
+
+            [[library]]
+            name = \"b\"
+            dependencies = [\"//foo:d\"]
+
+            [[library]]
+            name = \"c\"
+            dependencies = [\"//foo:d\"]
+
+            [[library]]
+            name = \"d\"
+            ",
+        );
+        BuildGraph::resolve(parse_target("foo:a").unwrap(), &root).unwrap()
+    }
+
+    fn target(target: &str) -> Target {
+        parse_target(target).unwrap()
+    }
+
+    #[test]
+    fn contains_every_resolved_target() {
+        let graph = diamond();
+        assert_eq!(graph.len(), 4);
+        assert!(graph.contains(&target("foo:d")));
+        assert!(!graph.contains(&target("foo:e")));
+        assert_eq!(graph.get(&target("foo:b")).unwrap().target, target("foo:b"));
+    }
+
+    #[test]
+    fn finds_targets_however_they_are_spelled() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"foo\"
+            ",
+        );
+        let graph = BuildGraph::resolve(target("foo"), &root).unwrap();
+        assert!(graph.contains(&target("foo:foo")));
+        assert_eq!(graph.topological_order()[0].target.to_string(), "foo:foo");
+    }
+
+    #[test]
+    fn topological_order_puts_dependencies_first() {
+        let graph = diamond();
+        let order = graph
+            .topological_order()
+            .iter()
+            .map(|node| node.target.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(order, Vec::from(["foo:d", "foo:b", "foo:c", "foo:a"]));
+    }
+
+    #[test]
+    fn finds_direct_dependencies() {
+        let graph = diamond();
+        assert_eq!(
+            names(graph.direct_dependencies(&target("foo:a"))),
+            Vec::from(["foo:b", "foo:c"])
+        );
+        assert!(graph.direct_dependencies(&target("foo:d")).is_empty());
+    }
+
+    #[test]
+    fn finds_transitive_dependencies() {
+        let graph = diamond();
+        assert_eq!(
+            names(graph.transitive_dependencies(&target("foo:a"))),
+            Vec::from(["foo:d", "foo:b", "foo:c"])
+        );
+        assert_eq!(
+            names(graph.transitive_dependencies(&target("foo:b"))),
+            Vec::from(["foo:d"])
+        );
+    }
+
+    #[test]
+    fn finds_direct_dependents() {
+        let graph = diamond();
+        assert_eq!(
+            names(graph.direct_dependents(&target("foo:d"))),
+            Vec::from(["foo:b", "foo:c"])
+        );
+        assert!(graph.direct_dependents(&target("foo:a")).is_empty());
+    }
+
+    #[test]
+    fn finds_transitive_dependents() {
+        let graph = diamond();
+        assert_eq!(
+            names(graph.transitive_dependents(&target("foo:d"))),
+            Vec::from(["foo:b", "foo:c", "foo:a"])
+        );
+    }
+
+    #[test]
+    fn queries_for_unknown_targets_are_empty() {
+        let graph = diamond();
+        assert!(graph.direct_dependencies(&target("foo:e")).is_empty());
+        assert!(graph.transitive_dependents(&target("foo:e")).is_empty());
+    }
+
+    #[test]
+    fn dedupes_nodes_when_merging_sorted_lists() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//foo:c\"]
+
+            [[library]]
+            name = \"b\"
+            dependencies = [\"//foo:c\"]
+
+            [[library]]
+            name = \"c\"
+            ",
+        );
+        let mut nodes = topologically_sort_dep_graph(target("foo:a"), &root).unwrap();
+        nodes.extend(topologically_sort_dep_graph(target("foo:b"), &root).unwrap());
+        let graph = BuildGraph::new(nodes);
+        assert_eq!(graph.len(), 3);
+        assert_eq!(
+            names(graph.direct_dependents(&target("foo:c"))),
+            Vec::from(["foo:a", "foo:b"])
+        );
+    }
+}
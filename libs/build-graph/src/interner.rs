@@ -0,0 +1,59 @@
+use std::collections::{hash_map::Entry, HashMap};
+use target::Target;
+
+/// A compact handle for a target, only meaningful to the interner that
+/// created it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct TargetId(u32);
+
+impl TargetId {
+    pub(crate) fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Hands out one id per distinct target, so per-target state can live in
+/// plain vectors instead of sets of strings.
+#[derive(Default)]
+pub(crate) struct Interner {
+    ids: HashMap<Target, TargetId>,
+    targets: Vec<Target>,
+}
+
+impl Interner {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn intern(&mut self, target: &Target) -> TargetId {
+        // `foo/bar` and `foo/bar:bar` are equal targets, so they share an id.
+        match self.ids.entry(target.clone()) {
+            Entry::Occupied(entry) => *entry.get(),
+            Entry::Vacant(entry) => {
+                let id = TargetId(self.targets.len() as u32);
+                self.targets.push(target.clone());
+                *entry.insert(id)
+            }
+        }
+    }
+
+    pub(crate) fn target(&self, id: TargetId) -> &Target {
+        &self.targets[id.index()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use target::parse::parse_target;
+
+    #[test]
+    fn interns_equal_targets_to_the_same_id() {
+        let mut interner = Interner::new();
+        let a = interner.intern(&parse_target("foo/bar").unwrap());
+        let b = interner.intern(&parse_target("foo:baz").unwrap());
+        assert_ne!(a, b);
+        assert_eq!(interner.intern(&parse_target("foo/bar:bar").unwrap()), a);
+        assert_eq!(interner.target(b).to_string(), "foo:baz");
+    }
+}
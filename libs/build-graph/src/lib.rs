@@ -1,4 +1,22 @@
+mod dependents;
+mod graph;
+mod interner;
+mod ownership;
+mod package_cache;
+mod scheduler;
+mod source_files;
+mod suggest;
 mod target_files;
 mod topological_sort;
 
-pub use topological_sort::topologically_sort_dep_graph;
+pub use dependents::{
+    find_all_dependents, find_dependents_mismatches, DependentsIndex, DependentsMismatch,
+};
+pub use graph::BuildGraph;
+pub use ownership::{check_source_files, find_unowned_files};
+pub use scheduler::{default_jobs, execute_in_parallel, TargetOutcome};
+pub use target_files::TargetFiles;
+pub use topological_sort::{
+    check_dep_graph, expand_target_pattern, topologically_sort_dep_graph,
+    topologically_sort_dep_graphs, DependencySortError,
+};
@@ -0,0 +1,218 @@
+use crate::{
+    target_files::TargetFiles,
+    topological_sort::{topologically_sort_dep_graph, DependencySortError},
+};
+use files::build_file::{TargetKind, BUILD_FILE_NAME, SOURCE_FILE_EXTENSION};
+use std::collections::{BTreeMap, BTreeSet};
+use target::{parse::parse_target, Target};
+use vfs::{VfsError, VfsPath};
+
+/// Where `file`, as listed by a target in `package`, is relative to the
+/// workspace root.
+fn workspace_path(package: &str, file: &str) -> String {
+    if package.is_empty() {
+        file.to_string()
+    } else {
+        format!("{package}/{file}")
+    }
+}
+
+/// The package below `package` that `file` is in, if any.
+fn subpackage_of(root: &VfsPath, package: &str, file: &str) -> Result<Option<String>, VfsError> {
+    let mut directory = package.to_string();
+    let Some((directories, _)) = file.rsplit_once('/') else {
+        return Ok(None);
+    };
+    for part in directories.split('/') {
+        directory = workspace_path(&directory, part);
+        if root
+            .join(format!("{directory}/{BUILD_FILE_NAME}"))?
+            .is_file()?
+        {
+            return Ok(Some(directory));
+        }
+    }
+    Ok(None)
+}
+
+/// Checks the source files of `targets`: each must exist and be inside the
+/// package of its target without reaching into a subpackage, and no file may
+/// belong to more than one of the libraries among `targets`.
+pub fn check_source_files(targets: &[TargetFiles], root: &VfsPath) -> Vec<DependencySortError> {
+    let mut errors = Vec::new();
+    let mut owners = BTreeMap::<String, Target>::new();
+    for target_files in targets {
+        let target = &target_files.target;
+        let package = target.get_directories();
+        for file in &target_files.files {
+            if file.starts_with('/') || file.split('/').any(|part| part == "..") {
+                errors.push(DependencySortError::FileOutsidePackage(
+                    target.clone(),
+                    file.clone(),
+                ));
+                continue;
+            }
+            match subpackage_of(root, package, file) {
+                Ok(Some(subpackage)) => {
+                    errors.push(DependencySortError::FileInSubpackage(
+                        target.clone(),
+                        file.clone(),
+                        subpackage,
+                    ));
+                    continue;
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    errors.push(DependencySortError::VfsError(error));
+                    continue;
+                }
+            }
+
+            let path = workspace_path(package, file);
+            match root.join(&path).and_then(|source| source.is_file()) {
+                Ok(true) => {}
+                Ok(false) => errors.push(DependencySortError::MissingSourceFile(
+                    target.clone(),
+                    path.clone(),
+                )),
+                Err(error) => errors.push(DependencySortError::VfsError(error)),
+            }
+            if target_files.definition.kind() != TargetKind::Library {
+                continue;
+            }
+            if let Some(owner) = owners.get(&path) {
+                errors.push(DependencySortError::FileOwnedTwice(
+                    path,
+                    owner.clone(),
+                    target.clone(),
+                ));
+            } else {
+                owners.insert(path, target.clone());
+            }
+        }
+    }
+    errors
+}
+
+/// Every source file in the workspace that no target lists, in path order.
+pub fn find_unowned_files(root: &VfsPath) -> Result<Vec<String>, DependencySortError> {
+    let everything = parse_target("...").unwrap();
+    let owned = topologically_sort_dep_graph(everything, root)?
+        .iter()
+        .flat_map(|target_files| {
+            let package = target_files.target.get_directories();
+            target_files
+                .files
+                .iter()
+                .map(move |file| workspace_path(package, file))
+        })
+        .collect::<BTreeSet<String>>();
+
+    let mut unowned = Vec::new();
+    for path in root.walk_dir().map_err(DependencySortError::VfsError)? {
+        let path = path.map_err(DependencySortError::VfsError)?;
+        if path.extension().as_deref() != Some(SOURCE_FILE_EXTENSION)
+            || !path.is_file().map_err(DependencySortError::VfsError)?
+        {
+            continue;
+        }
+        let location = path
+            .as_str()
+            .strip_prefix(root.as_str())
+            .unwrap_or_default()
+            .trim_start_matches('/');
+        if !owned.contains(location) {
+            unowned.push(location.to_string());
+        }
+    }
+    unowned.sort();
+    Ok(unowned)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::topological_sort::topologically_sort_dep_graphs;
+    use test_utils::create_file::create_test_file;
+    use vfs::MemoryFS;
+
+    fn workspace() -> VfsPath {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            files = [\"a.buri\", \"shared.buri\", \"missing.buri\"]
+
+            [[library]]
+            name = \"b\"
+            files = [\"shared.buri\", \"../bar/b.buri\", \"sub/c.buri\"]
+
+            [[test]]
+            name = \"a_test\"
+            files = [\"a.buri\", \"a_test.buri\"]
+            dependencies = [\":a\"]
+            ",
+        );
+        create_test_file(&root, "foo/sub/BUILD.toml", b"");
+        for file in [
+            "foo/a.buri",
+            "foo/a_test.buri",
+            "foo/shared.buri",
+            "foo/stray.buri",
+            "foo/sub/c.buri",
+            "bar/b.buri",
+            "foo/notes.txt",
+        ] {
+            create_test_file(&root, file, b"");
+        }
+        root
+    }
+
+    #[test]
+    fn reports_every_problem_with_source_files() {
+        let root = workspace();
+        let targets =
+            topologically_sort_dep_graphs(&[parse_target("foo:...").unwrap()], &[], &root).unwrap();
+        let errors = check_source_files(&targets, &root)
+            .iter()
+            .map(|error| error.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            errors,
+            Vec::from([
+                "Source file foo/missing.buri of foo:a does not exist",
+                "Source file ../bar/b.buri of foo:b is outside its package",
+                "Source file foo/shared.buri belongs to both foo:a and foo:b",
+                "Source file sub/c.buri of foo:b belongs to package foo/sub",
+            ])
+        );
+    }
+
+    #[test]
+    fn finds_files_no_target_lists() {
+        let root = workspace();
+        // Fix the file lists so the graph resolves.
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            files = [\"a.buri\"]
+            ",
+        );
+        assert_eq!(
+            find_unowned_files(&root).unwrap(),
+            Vec::from([
+                "bar/b.buri",
+                "foo/a_test.buri",
+                "foo/shared.buri",
+                "foo/stray.buri",
+                "foo/sub/c.buri"
+            ])
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{
+    source_files::package_files, suggest::suggest_targets, topological_sort::DependencySortError,
+};
+use files::{
+    build_file::{build_file_location, BuildFile},
+    diagnostic::{parse_toml, Diagnostic},
+};
+use std::{collections::HashMap, rc::Rc};
+use target::Target;
+use vfs::{VfsError, VfsPath};
+
+pub(crate) fn parse_build_file(
+    build_file_path: &VfsPath,
+) -> Result<BuildFile, DependencySortError> {
+    let contents = build_file_path
+        .read_to_string()
+        .map_err(DependencySortError::VfsError)?;
+    let path = build_file_path.as_str().trim_start_matches('/');
+    parse_toml::<BuildFile>(path, &contents)
+        .map_err(|diagnostic| DependencySortError::BuildFileParseError(Box::new(diagnostic)))
+}
+
+/// Parsed build files keyed by package directory, so each build file is read
+/// and parsed once no matter how many of its targets are resolved. Build files
+/// that fail to parse are cached too, as the diagnostic they failed with.
+/// The files in each package are listed once as well.
+#[derive(Default)]
+pub(crate) struct PackageCache {
+    packages: HashMap<String, Result<Rc<BuildFile>, Box<Diagnostic>>>,
+    /// Keyed by the path of the package directory.
+    files: HashMap<String, Rc<Vec<String>>>,
+}
+
+impl PackageCache {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// The package `target` is defined in.
+    pub(crate) fn package_of(
+        &mut self,
+        target: &Target,
+        root: &VfsPath,
+    ) -> Result<Rc<BuildFile>, DependencySortError> {
+        if let Some(package) = self.packages.get(target.get_directories()) {
+            return cached(package);
+        }
+        let build_file_path = root
+            .join(build_file_location(target))
+            .map_err(DependencySortError::VfsError)?;
+        if !build_file_path
+            .exists()
+            .map_err(DependencySortError::VfsError)?
+        {
+            return Err(DependencySortError::BuildFileNotFound(
+                target.clone(),
+                suggest_targets(target, root),
+            ));
+        }
+        self.package_at(target.get_directories(), &build_file_path)
+    }
+
+    /// The package in `directory`, whose build file is at `build_file_path`.
+    pub(crate) fn package_at(
+        &mut self,
+        directory: &str,
+        build_file_path: &VfsPath,
+    ) -> Result<Rc<BuildFile>, DependencySortError> {
+        if let Some(package) = self.packages.get(directory) {
+            return cached(package);
+        }
+        let package = match parse_build_file(build_file_path) {
+            Ok(build_file) => Ok(Rc::new(build_file)),
+            Err(DependencySortError::BuildFileParseError(diagnostic)) => Err(diagnostic),
+            // Anything else, such as failing to read the file, is not cached.
+            Err(error) => return Err(error),
+        };
+        self.packages.insert(directory.to_string(), package.clone());
+        cached(&package)
+    }
+
+    /// Every file in `package` outside its subpackages, relative to it.
+    pub(crate) fn files_in(&mut self, package: &VfsPath) -> Result<Rc<Vec<String>>, VfsError> {
+        if let Some(files) = self.files.get(package.as_str()) {
+            return Ok(files.clone());
+        }
+        let mut files = Vec::new();
+        package_files(package, package, &mut files)?;
+        let files = Rc::new(files);
+        self.files
+            .insert(package.as_str().to_string(), files.clone());
+        Ok(files)
+    }
+}
+
+/// A cached package, with a parse failure turned back into its error.
+fn cached(
+    package: &Result<Rc<BuildFile>, Box<Diagnostic>>,
+) -> Result<Rc<BuildFile>, DependencySortError> {
+    package
+        .clone()
+        .map_err(DependencySortError::BuildFileParseError)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use target::parse::parse_target;
+    use test_utils::create_file::create_test_file;
+    use vfs::MemoryFS;
+
+    #[test]
+    fn parses_each_build_file_once() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            ",
+        );
+        let mut packages = PackageCache::new();
+        let first = packages
+            .package_of(&parse_target("foo:a").unwrap(), &root)
+            .unwrap();
+        // Later reads come from the cache even if the file changes.
+        create_test_file(&root, "foo/BUILD.toml", b"");
+        let second = packages
+            .package_of(&parse_target("foo:b").unwrap(), &root)
+            .unwrap();
+        assert!(Rc::ptr_eq(&first, &second));
+        assert_eq!(second.library.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn parses_each_broken_build_file_once() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(&root, "foo/BUILD.toml", b"[[library]]\nname = 1\n");
+        let mut packages = PackageCache::new();
+        let first = packages.package_of(&parse_target("foo:a").unwrap(), &root);
+        assert!(matches!(
+            first,
+            Err(DependencySortError::BuildFileParseError(_))
+        ));
+        // The error is cached even once the file is fixed.
+        create_test_file(&root, "foo/BUILD.toml", b"");
+        let second = packages.package_of(&parse_target("foo:b").unwrap(), &root);
+        assert_eq!(
+            first.unwrap_err().to_string(),
+            second.unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn errors_if_build_file_does_not_exist() {
+        let root: VfsPath = MemoryFS::new().into();
+        let mut packages = PackageCache::new();
+        let result = packages.package_of(&parse_target("foo:a").unwrap(), &root);
+        assert!(matches!(
+            result,
+            Err(DependencySortError::BuildFileNotFound(_, _))
+        ));
+    }
+}
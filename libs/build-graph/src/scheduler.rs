@@ -0,0 +1,371 @@
+use crate::{graph::BuildGraph, target_files::TargetFiles};
+use std::{
+    any::Any,
+    collections::VecDeque,
+    num::NonZeroUsize,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum TargetOutcome<T, E> {
+    Succeeded(T),
+    Failed(E),
+    /// The action panicked, with the panic message.
+    Panicked(String),
+    /// One of the target's dependencies failed, so it was never started.
+    Skipped,
+}
+
+/// The message a panic was started with, if it was given one.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Number of worker threads to use when the user does not ask for a specific
+/// amount.
+pub fn default_jobs() -> usize {
+    thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+}
+
+/// Runs `action` for every target in the graph on a pool of at most `jobs`
+/// worker threads.
+///
+/// A target only starts once all of its dependencies have succeeded. If a
+/// target fails or its action panics, everything that transitively depends on
+/// it is skipped.
+/// `on_finished` is called on the calling thread in completion order, so it
+/// does not need to be thread safe.
+///
+/// Returns the outcome of every target, in the graph's topological order.
+pub fn execute_in_parallel<T, E, A, F>(
+    graph: &BuildGraph,
+    jobs: usize,
+    action: A,
+    mut on_finished: F,
+) -> Vec<TargetOutcome<T, E>>
+where
+    T: Send,
+    E: Send,
+    A: Fn(&TargetFiles) -> Result<T, E> + Sync,
+    F: FnMut(&TargetFiles, &TargetOutcome<T, E>),
+{
+    let targets = graph.topological_order();
+    let mut remaining_dependencies = (0..targets.len())
+        .map(|index| graph.dependency_indices(index).len())
+        .collect::<Vec<usize>>();
+
+    let mut ready = remaining_dependencies
+        .iter()
+        .enumerate()
+        .filter(|(_, remaining)| **remaining == 0)
+        .map(|(index, _)| index)
+        .collect::<VecDeque<usize>>();
+    let mut outcomes: Vec<Option<TargetOutcome<T, E>>> = targets.iter().map(|_| None).collect();
+    let jobs = jobs.max(1);
+
+    thread::scope(|scope| {
+        let (job_sender, job_receiver) = mpsc::channel::<usize>();
+        let (result_sender, result_receiver) = mpsc::channel::<(usize, TargetOutcome<T, E>)>();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        for _ in 0..jobs.min(targets.len()) {
+            let job_receiver = Arc::clone(&job_receiver);
+            let result_sender = result_sender.clone();
+            let action = &action;
+            scope.spawn(move || loop {
+                let job = job_receiver.lock().unwrap().recv();
+                match job {
+                    Ok(index) => {
+                        // A panic is reported like a failure so the worker
+                        // lives on and the scheduler still hears back.
+                        let outcome =
+                            match catch_unwind(AssertUnwindSafe(|| action(&targets[index]))) {
+                                Ok(Ok(value)) => TargetOutcome::Succeeded(value),
+                                Ok(Err(error)) => TargetOutcome::Failed(error),
+                                Err(payload) => TargetOutcome::Panicked(panic_message(&*payload)),
+                            };
+                        if result_sender.send((index, outcome)).is_err() {
+                            return;
+                        }
+                    }
+                    // The scheduler is done handing out work.
+                    Err(_) => return,
+                }
+            });
+        }
+        // Only the workers hold senders now, so the channel closes once they
+        // have all exited.
+        drop(result_sender);
+
+        let mut in_flight = 0;
+        loop {
+            while in_flight < jobs {
+                match ready.pop_front() {
+                    Some(index) => {
+                        job_sender.send(index).unwrap();
+                        in_flight += 1;
+                    }
+                    None => break,
+                }
+            }
+            if in_flight == 0 {
+                break;
+            }
+
+            let (index, outcome) = result_receiver.recv().unwrap();
+            in_flight -= 1;
+            let failed = !matches!(outcome, TargetOutcome::Succeeded(_));
+            if !failed {
+                for dependent in graph.dependent_indices(index) {
+                    remaining_dependencies[*dependent] -= 1;
+                    if remaining_dependencies[*dependent] == 0 {
+                        ready.push_back(*dependent);
+                    }
+                }
+            }
+            on_finished(&targets[index], &outcome);
+            outcomes[index] = Some(outcome);
+
+            if failed {
+                let mut to_skip = graph.dependent_indices(index).to_vec();
+                while let Some(dependent) = to_skip.pop() {
+                    if outcomes[dependent].is_none() {
+                        on_finished(&targets[dependent], &TargetOutcome::Skipped);
+                        outcomes[dependent] = Some(TargetOutcome::Skipped);
+                        to_skip.extend(graph.dependent_indices(dependent).iter());
+                    }
+                }
+            }
+        }
+        // Closing the channel lets the workers exit.
+        drop(job_sender);
+    });
+
+    outcomes
+        .into_iter()
+        .map(|outcome| outcome.unwrap_or(TargetOutcome::Skipped))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use files::build_file::{Library, TargetDefinition};
+    use std::{sync::Barrier, thread::sleep, time::Duration};
+    use target::parse::parse_target;
+
+    fn target_files(target: &str, dependencies: &[&str]) -> TargetFiles {
+        let target = parse_target(target).unwrap();
+        TargetFiles {
+            definition: TargetDefinition::Library(Library {
+                name: target.name().to_string(),
+                files: None,
+                dependencies: None,
+                dependents: None,
+                visibility: None,
+            }),
+            target,
+            files: Vec::new(),
+            dependencies: dependencies
+                .iter()
+                .map(|dependency| parse_target(dependency).unwrap())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn runs_every_target() {
+        let targets = Vec::from([
+            target_files("foo:a", &[]),
+            target_files("foo:b", &[]),
+            target_files("foo:c", &["foo:a", "foo:b"]),
+        ]);
+        let graph = BuildGraph::new(targets);
+        let outcomes = execute_in_parallel(&graph, 4, |_| Ok::<(), ()>(()), |_, _| {});
+        assert_eq!(
+            outcomes,
+            Vec::from([
+                TargetOutcome::Succeeded(()),
+                TargetOutcome::Succeeded(()),
+                TargetOutcome::Succeeded(())
+            ])
+        );
+    }
+
+    #[test]
+    fn starts_targets_after_their_dependencies_finish() {
+        let targets = Vec::from([
+            target_files("foo:d", &[]),
+            target_files("foo:b", &["foo:d"]),
+            target_files("foo:c", &["foo:d"]),
+            target_files("foo:a", &["foo:b", "foo:c"]),
+        ]);
+        let graph = BuildGraph::new(targets);
+        let events = Mutex::new(Vec::new());
+        execute_in_parallel(
+            &graph,
+            4,
+            |target_files| {
+                events
+                    .lock()
+                    .unwrap()
+                    .push(format!("start {}", target_files.target));
+                sleep(Duration::from_millis(10));
+                events
+                    .lock()
+                    .unwrap()
+                    .push(format!("end {}", target_files.target));
+                Ok::<(), ()>(())
+            },
+            |_, _| {},
+        );
+        let events = events.into_inner().unwrap();
+        let position = |event: &str| events.iter().position(|e| e == event).unwrap();
+        for target_files in graph.topological_order() {
+            for dependency in &target_files.dependencies {
+                assert!(
+                    position(&format!("end {dependency}"))
+                        < position(&format!("start {}", target_files.target))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn runs_independent_targets_concurrently() {
+        let targets = Vec::from([target_files("foo:a", &[]), target_files("foo:b", &[])]);
+        let graph = BuildGraph::new(targets);
+        // Neither action can finish until both have started.
+        let barrier = Barrier::new(2);
+        let outcomes = execute_in_parallel(
+            &graph,
+            2,
+            |_| {
+                barrier.wait();
+                Ok::<(), ()>(())
+            },
+            |_, _| {},
+        );
+        assert_eq!(
+            outcomes,
+            Vec::from([TargetOutcome::Succeeded(()), TargetOutcome::Succeeded(())])
+        );
+    }
+
+    #[test]
+    fn never_runs_more_than_the_job_limit() {
+        let targets = (0..8)
+            .map(|index| target_files(&format!("foo:t{index}"), &[]))
+            .collect::<Vec<TargetFiles>>();
+        let graph = BuildGraph::new(targets);
+        let active = Mutex::new(0);
+        let max_active = Mutex::new(0);
+        execute_in_parallel(
+            &graph,
+            3,
+            |_| {
+                {
+                    let mut active = active.lock().unwrap();
+                    *active += 1;
+                    let mut max_active = max_active.lock().unwrap();
+                    *max_active = (*max_active).max(*active);
+                }
+                sleep(Duration::from_millis(10));
+                *active.lock().unwrap() -= 1;
+                Ok::<(), ()>(())
+            },
+            |_, _| {},
+        );
+        assert!(max_active.into_inner().unwrap() <= 3);
+    }
+
+    #[test]
+    fn skips_dependents_of_failed_targets() {
+        let targets = Vec::from([
+            target_files("foo:c", &[]),
+            target_files("foo:d", &[]),
+            target_files("foo:b", &["foo:c"]),
+            target_files("foo:a", &["foo:b", "foo:d"]),
+        ]);
+        let graph = BuildGraph::new(targets);
+        let outcomes = execute_in_parallel(
+            &graph,
+            1,
+            |target_files| {
+                if target_files.target.to_string() == "foo:c" {
+                    Err("boom")
+                } else {
+                    Ok(())
+                }
+            },
+            |_, _| {},
+        );
+        assert_eq!(
+            outcomes,
+            Vec::from([
+                TargetOutcome::Failed("boom"),
+                TargetOutcome::Succeeded(()),
+                TargetOutcome::Skipped,
+                TargetOutcome::Skipped,
+            ])
+        );
+    }
+
+    #[test]
+    fn reports_panicking_actions_and_skips_their_dependents() {
+        let targets = Vec::from([
+            target_files("foo:a", &[]),
+            target_files("foo:b", &[]),
+            target_files("foo:c", &["foo:a"]),
+        ]);
+        let graph = BuildGraph::new(targets);
+        let outcomes = execute_in_parallel(
+            &graph,
+            2,
+            |target_files| {
+                if target_files.target.to_string() == "foo:a" {
+                    panic!("boom");
+                }
+                Ok::<(), ()>(())
+            },
+            |_, _| {},
+        );
+        assert_eq!(
+            outcomes,
+            Vec::from([
+                TargetOutcome::Panicked("boom".to_string()),
+                TargetOutcome::Succeeded(()),
+                TargetOutcome::Skipped,
+            ])
+        );
+    }
+
+    #[test]
+    fn reports_every_target_once() {
+        let targets = Vec::from([
+            target_files("foo:c", &[]),
+            target_files("foo:b", &["foo:c"]),
+            target_files("foo:a", &["foo:b", "foo:c"]),
+        ]);
+        let graph = BuildGraph::new(targets);
+        let mut reported = Vec::new();
+        execute_in_parallel(
+            &graph,
+            2,
+            |_| Err::<(), ()>(()),
+            |target_files, _| reported.push(target_files.target.to_string()),
+        );
+        reported.sort();
+        assert_eq!(reported, Vec::from(["foo:a", "foo:b", "foo:c"]));
+    }
+}
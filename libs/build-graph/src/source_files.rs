@@ -0,0 +1,221 @@
+use crate::package_cache::PackageCache;
+use files::build_file::BUILD_FILE_NAME;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::{collections::BTreeSet, fmt};
+use vfs::{VfsError, VfsPath};
+
+#[derive(Debug)]
+pub enum SourceFilesError {
+    /// Pattern, why it is invalid
+    InvalidPattern(String, String),
+    VfsError(VfsError),
+}
+
+impl fmt::Display for SourceFilesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidPattern(pattern, error) => {
+                write!(f, "invalid file pattern `{pattern}`: {error}")
+            }
+            Self::VfsError(error) => write!(f, "file system error: {error}"),
+        }
+    }
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[', '{'])
+}
+
+fn build_glob_set(patterns: &[&str]) -> Result<GlobSet, SourceFilesError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            // `*` stays within a directory, only `**` crosses into subdirectories.
+            .literal_separator(true)
+            .build()
+            .map_err(|error| {
+                SourceFilesError::InvalidPattern(pattern.to_string(), error.kind().to_string())
+            })?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|error| {
+        SourceFilesError::InvalidPattern(patterns.join(", "), error.kind().to_string())
+    })
+}
+
+/// Every file in `directory` and its subdirectories, relative to `package`.
+/// Subdirectories with their own build file belong to another package, so
+/// they are not entered.
+pub(crate) fn package_files(
+    package: &VfsPath,
+    directory: &VfsPath,
+    files: &mut Vec<String>,
+) -> Result<(), VfsError> {
+    for entry in directory.read_dir()? {
+        if entry.is_dir()? {
+            if !entry.join(BUILD_FILE_NAME)?.is_file()? {
+                package_files(package, &entry, files)?;
+            }
+        } else {
+            let relative = entry
+                .as_str()
+                .strip_prefix(package.as_str())
+                .unwrap_or_default()
+                .trim_start_matches('/');
+            files.push(relative.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Expands the `files` of a target into the sorted list of source files it
+/// names, relative to `package`, the directory of its build file.
+///
+/// Patterns such as `src/**/*.buri` match files in the package, and patterns
+/// starting with `!` remove the files they match. Plain file names are kept
+/// even if the file does not exist, so building reports it as missing. The
+/// files in the package are listed once per `packages`, however many targets
+/// of the package use globs.
+pub(crate) fn expand_source_files(
+    packages: &mut PackageCache,
+    package: &VfsPath,
+    patterns: &[String],
+) -> Result<Vec<String>, SourceFilesError> {
+    let (excluded, included): (Vec<&str>, Vec<&str>) = patterns
+        .iter()
+        .map(String::as_str)
+        .partition(|pattern| pattern.starts_with('!'));
+    let excluded = build_glob_set(
+        &excluded
+            .iter()
+            .map(|pattern| &pattern[1..])
+            .collect::<Vec<&str>>(),
+    )?;
+    let globs = build_glob_set(
+        &included
+            .iter()
+            .copied()
+            .filter(|pattern| is_glob(pattern))
+            .collect::<Vec<&str>>(),
+    )?;
+
+    let mut files = included
+        .iter()
+        .filter(|pattern| !is_glob(pattern))
+        .map(|file| file.to_string())
+        .collect::<BTreeSet<String>>();
+    if !globs.is_empty() {
+        let candidates = packages
+            .files_in(package)
+            .map_err(SourceFilesError::VfsError)?;
+        files.extend(
+            candidates
+                .iter()
+                .filter(|candidate| globs.is_match(candidate))
+                .cloned(),
+        );
+    }
+    Ok(files
+        .into_iter()
+        .filter(|file| !excluded.is_match(file))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::create_file::create_test_file;
+    use vfs::MemoryFS;
+
+    fn package() -> VfsPath {
+        let root: VfsPath = MemoryFS::new().into();
+        for file in [
+            "app/BUILD.toml",
+            "app/src/main.buri",
+            "app/src/main_test.buri",
+            "app/src/util/strings.buri",
+            "app/src/util/strings_test.buri",
+            "app/src/README.md",
+            "app/src/nested/BUILD.toml",
+            "app/src/nested/other.buri",
+        ] {
+            create_test_file(&root, file, b"");
+        }
+        root.join("app").unwrap()
+    }
+
+    fn expand(patterns: &[&str]) -> Vec<String> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .collect::<Vec<String>>();
+        expand_source_files(&mut PackageCache::new(), &package(), &patterns).unwrap()
+    }
+
+    #[test]
+    fn expands_globs_in_sorted_order() {
+        assert_eq!(
+            expand(&["src/**/*.buri"]),
+            Vec::from([
+                "src/main.buri",
+                "src/main_test.buri",
+                "src/util/strings.buri",
+                "src/util/strings_test.buri",
+            ])
+        );
+        assert_eq!(
+            expand(&["src/*.buri"]),
+            Vec::from(["src/main.buri", "src/main_test.buri"])
+        );
+    }
+
+    #[test]
+    fn excludes_files_matching_negated_patterns() {
+        assert_eq!(
+            expand(&["src/**/*.buri", "!src/**/*_test.buri"]),
+            Vec::from(["src/main.buri", "src/util/strings.buri"])
+        );
+        assert_eq!(
+            expand(&["src/main.buri", "!src/main.buri"]),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn keeps_plain_file_names_even_if_missing() {
+        assert_eq!(
+            expand(&["src/missing.buri", "src/main.buri", "src/main.buri"]),
+            Vec::from(["src/main.buri", "src/missing.buri"])
+        );
+    }
+
+    #[test]
+    fn does_not_enter_other_packages() {
+        assert!(!expand(&["**"]).contains(&"src/nested/other.buri".to_string()));
+    }
+
+    #[test]
+    fn lists_the_files_of_a_package_once() {
+        let package = package();
+        let mut packages = PackageCache::new();
+        let patterns = Vec::from(["src/*.buri".to_string()]);
+        let first = expand_source_files(&mut packages, &package, &patterns).unwrap();
+        // Later expansions come from the cache even if the files change.
+        create_test_file(&package, "src/added.buri", b"");
+        let second = expand_source_files(&mut packages, &package, &patterns).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn errors_on_invalid_patterns() {
+        let result = expand_source_files(
+            &mut PackageCache::new(),
+            &package(),
+            &["src/[a.buri".to_string()],
+        );
+        assert!(matches!(
+            result,
+            Err(SourceFilesError::InvalidPattern(pattern, _)) if pattern == "src/[a.buri"
+        ));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{package_cache::parse_build_file, topological_sort::DependencySortError};
+use files::build_file::BUILD_FILE_NAME;
+use target::{parse::parse_target, Target};
+use vfs::VfsPath;
+
+const MAX_SUGGESTIONS: usize = 3;
+/// How many directory levels below the starting directory to look for
+/// packages. Suggestions are only computed for errors, but should still not
+/// walk a large repository.
+const SEARCH_DEPTH: usize = 2;
+
+fn join(root: &VfsPath, directory: &str) -> vfs::VfsResult<VfsPath> {
+    match directory {
+        "" => Ok(root.clone()),
+        _ => root.join(directory),
+    }
+}
+
+fn parent_directory(directory: &str) -> &str {
+    directory.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+/// Every library defined in a build file at `directory` or up to
+/// `SEARCH_DEPTH` levels below it.
+fn libraries_near(directory: &str, root: &VfsPath, targets: &mut Vec<Target>) {
+    let mut directories = Vec::from([(directory.to_string(), 0)]);
+    while let Some((directory, depth)) = directories.pop() {
+        let Ok(path) = join(root, &directory) else {
+            continue;
+        };
+        let build_file = path
+            .join(BUILD_FILE_NAME)
+            .map_err(DependencySortError::VfsError)
+            .and_then(|build_file_path| parse_build_file(&build_file_path));
+        if let Ok(package) = build_file {
+            for name in package.target_names() {
+                if let Ok(target) = parse_target(&format!("{directory}:{name}")) {
+                    targets.push(target);
+                }
+            }
+        }
+        if depth == SEARCH_DEPTH {
+            continue;
+        }
+        for child in path.read_dir().into_iter().flatten() {
+            if child.is_dir().unwrap_or(false) {
+                let child_directory = match directory.as_str() {
+                    "" => child.filename(),
+                    _ => format!("{directory}/{}", child.filename()),
+                };
+                directories.push((child_directory, depth + 1));
+            }
+        }
+    }
+}
+
+/// Finds existing targets whose names are close to `target`, closest first.
+/// Swapped characters count as a single typo.
+/// Looks in the target's own package, its sibling packages and their
+/// subpackages. If the package does not exist, looks around its closest
+/// existing ancestor instead.
+pub(crate) fn suggest_targets(target: &Target, root: &VfsPath) -> Vec<Target> {
+    let mut directory = target.get_directories();
+    while !directory.is_empty()
+        && !join(root, directory)
+            .and_then(|path| path.is_dir())
+            .unwrap_or(false)
+    {
+        directory = parent_directory(directory);
+    }
+    let directory = if directory == target.get_directories() {
+        parent_directory(directory)
+    } else {
+        directory
+    };
+
+    let mut candidates = Vec::new();
+    libraries_near(directory, root, &mut candidates);
+
+    let wanted = target.to_string();
+    // Allow roughly one typo for every three characters.
+    let max_distance = (wanted.len() / 3).max(2);
+    let mut suggestions = candidates
+        .into_iter()
+        .map(|candidate| {
+            (
+                strsim::osa_distance(&wanted, &candidate.to_string()),
+                candidate,
+            )
+        })
+        .filter(|(distance, candidate)| *distance <= max_distance && *candidate != *target)
+        .collect::<Vec<(usize, Target)>>();
+    suggestions.sort_by_key(|(distance, candidate)| (*distance, candidate.to_string()));
+    suggestions.dedup_by(|(_, a), (_, b)| a == b);
+    suggestions
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::create_file::create_test_file;
+    use vfs::MemoryFS;
+
+    fn workspace() -> VfsPath {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/bar/BUILD.toml",
+            b"
+            [[library]]
+            name = \"baz\"
+
+            [[library]]
+            name = \"bat\"
+
+            [[library]]
+            name = \"unrelated\"
+            ",
+        );
+        create_test_file(
+            &root,
+            "foo/qux/BUILD.toml",
+            b"
+            [[library]]
+            name = \"baz\"
+            ",
+        );
+        root
+    }
+
+    fn suggest(target: &str) -> Vec<String> {
+        suggest_targets(&parse_target(target).unwrap(), &workspace())
+            .iter()
+            .map(|target| target.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn suggests_libraries_in_the_same_build_file() {
+        assert_eq!(
+            suggest("foo/bar:bax"),
+            Vec::from(["foo/bar:bat", "foo/bar:baz"])
+        );
+    }
+
+    #[test]
+    fn suggests_libraries_in_nearby_packages() {
+        assert_eq!(
+            suggest("foo/qix:baz"),
+            Vec::from(["foo/qux:baz", "foo/bar:baz"])
+        );
+    }
+
+    #[test]
+    fn suggests_libraries_when_the_package_does_not_exist() {
+        assert_eq!(
+            suggest("foo/baz:baz"),
+            Vec::from(["foo/bar:baz", "foo/bar:bat", "foo/qux:baz"])
+        );
+    }
+
+    #[test]
+    fn does_not_suggest_distant_names() {
+        assert!(suggest("foo/bar:something").is_empty());
+    }
+}
@@ -1,7 +1,12 @@
+use files::build_file::TargetDefinition;
 use target::Target;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct TargetFiles {
     pub target: Target,
     pub files: Vec<String>,
+    /// direct dependencies of this target
+    pub dependencies: Vec<Target>,
+    /// definition of the target as written in its build file
+    pub definition: TargetDefinition,
 }
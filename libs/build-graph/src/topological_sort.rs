@@ -1,110 +1,550 @@
-use crate::target_files::TargetFiles;
-use files::build_file::{BuildFile, Library};
-use std::collections::HashSet;
+use crate::{
+    interner::{Interner, TargetId},
+    package_cache::PackageCache,
+    source_files::{expand_source_files, SourceFilesError},
+    suggest::suggest_targets,
+    target_files::TargetFiles,
+};
+use files::{
+    build_file::{
+        build_file_location, visibility_allows, BuildFile, TargetDefinition, TargetKind,
+        BUILD_FILE_NAME,
+    },
+    diagnostic::Diagnostic,
+};
+use std::fmt;
 use target::{
     parse::{parse_target, TargetParseError},
     Target,
 };
-use toml::de::Error;
 use vfs::{VfsError, VfsPath};
 
-type Visited = HashSet<String>;
-
 #[derive(Debug)]
 pub enum DependencySortError {
-    CyclicDependency(Target),
+    /// Every target on the cycle in dependency order, starting and ending with
+    /// the same target. Each edge is declared in the build file of the target
+    /// it starts from.
+    CyclicDependency(Vec<Target>),
     VfsError(VfsError),
-    BuildFileParseError(Error),
+    BuildFileParseError(Box<Diagnostic>),
     ParseTargetError(TargetParseError),
-    TargetNotFoundInBuildFile(Target),
+    /// Missing target, closest existing targets
+    TargetNotFoundInBuildFile(Target, Vec<Target>),
+    /// Target whose build file does not exist, closest existing targets
+    BuildFileNotFound(Target, Vec<Target>),
+    /// A target that breaks the rules of its kind
+    InvalidDefinition(Box<Diagnostic>),
+    /// Dependent, its kind, dependency, its kind
+    ForbiddenDependency(Target, TargetKind, Target, TargetKind),
+    /// Target, pattern in its `files`, why the pattern is invalid
+    InvalidFilePattern(Target, String, String),
+    /// Target, workspace relative path of the missing file
+    MissingSourceFile(Target, String),
+    /// Target, file as listed by the target
+    FileOutsidePackage(Target, String),
+    /// Target, file as listed by the target, package the file is in
+    FileInSubpackage(Target, String, String),
+    /// Workspace relative path of the file, first library, second library
+    FileOwnedTwice(String, Target, Target),
+    /// Dependent, dependency, visibility of the dependency
+    NotVisible(Target, Target, Vec<Target>),
 }
 
-fn resolve_build_file(target: &Target, root: &VfsPath) -> Result<BuildFile, DependencySortError> {
-    let build_file_path = root
-        .join(target.build_file_location())
-        .map_err(DependencySortError::VfsError)?;
-    let build_file = build_file_path
-        .read_to_string()
+impl fmt::Display for DependencySortError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::CyclicDependency(cycle) => write!(
+                f,
+                "Dependency cycle detected: {}",
+                cycle
+                    .iter()
+                    .map(|target| target.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" -> ")
+            ),
+            Self::VfsError(error) => write!(f, "File system error: {error}"),
+            Self::BuildFileParseError(diagnostic) => write!(f, "{diagnostic}"),
+            Self::ParseTargetError(error) => write!(f, "Invalid target: {error}"),
+            Self::TargetNotFoundInBuildFile(target, _) => write!(
+                f,
+                "Target {target} not found in {}",
+                build_file_location(target)
+            ),
+            Self::BuildFileNotFound(target, _) => write!(
+                f,
+                "Build file {} for target {target} does not exist",
+                build_file_location(target)
+            ),
+            Self::InvalidDefinition(diagnostic) => write!(f, "{diagnostic}"),
+            Self::ForbiddenDependency(dependent, dependent_kind, dependency, dependency_kind) => {
+                write!(
+                    f,
+                    "{dependent_kind} {dependent} cannot depend on {dependency_kind} {dependency}"
+                )
+            }
+            Self::InvalidFilePattern(target, pattern, error) => {
+                write!(f, "Invalid file pattern `{pattern}` in {target}: {error}")
+            }
+            Self::MissingSourceFile(target, path) => {
+                write!(f, "Source file {path} of {target} does not exist")
+            }
+            Self::FileOutsidePackage(target, file) => {
+                write!(f, "Source file {file} of {target} is outside its package")
+            }
+            Self::FileInSubpackage(target, file, package) => {
+                write!(
+                    f,
+                    "Source file {file} of {target} belongs to package {package}"
+                )
+            }
+            Self::FileOwnedTwice(path, first, second) => {
+                write!(f, "Source file {path} belongs to both {first} and {second}")
+            }
+            Self::NotVisible(dependent, dependency, visibility) => write!(
+                f,
+                "{dependent} cannot depend on {dependency}, which is only visible to {}",
+                visibility
+                    .iter()
+                    .map(|rule| format!("//{rule}"))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+impl DependencySortError {
+    /// The build file the error is about, if any.
+    pub fn file(&self) -> Option<String> {
+        match self {
+            Self::CyclicDependency(cycle) => cycle.first().map(build_file_location),
+            Self::VfsError(_) | Self::ParseTargetError(_) => None,
+            Self::BuildFileParseError(diagnostic) | Self::InvalidDefinition(diagnostic) => {
+                Some(diagnostic.path.clone())
+            }
+            Self::TargetNotFoundInBuildFile(target, _)
+            | Self::BuildFileNotFound(target, _)
+            | Self::ForbiddenDependency(target, _, _, _)
+            | Self::InvalidFilePattern(target, _, _)
+            | Self::MissingSourceFile(target, _)
+            | Self::FileOutsidePackage(target, _)
+            | Self::FileInSubpackage(target, _, _)
+            | Self::FileOwnedTwice(_, _, target)
+            | Self::NotVisible(target, _, _) => Some(build_file_location(target)),
+        }
+    }
+}
+
+/// Finds every package at or below the directory of a recursive target, as
+/// its directory and the path of its build file, in path order.
+pub(crate) fn find_packages(
+    target: &Target,
+    root: &VfsPath,
+) -> Result<Vec<(String, VfsPath)>, DependencySortError> {
+    let directory = root
+        .join(target.get_directories())
         .map_err(DependencySortError::VfsError)?;
-    toml::from_str::<BuildFile>(&build_file).map_err(DependencySortError::BuildFileParseError)
+    let mut build_file_paths = Vec::new();
+    for path in directory
+        .walk_dir()
+        .map_err(DependencySortError::VfsError)?
+    {
+        let path = path.map_err(DependencySortError::VfsError)?;
+        if path.filename() == BUILD_FILE_NAME && path.is_file().unwrap_or(false) {
+            build_file_paths.push(path);
+        }
+    }
+    build_file_paths.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    Ok(build_file_paths
+        .into_iter()
+        .map(|build_file_path| {
+            let package = build_file_path
+                .parent()
+                .as_str()
+                .strip_prefix(root.as_str())
+                .unwrap_or_default()
+                .trim_start_matches('/')
+                .to_string();
+            (package, build_file_path)
+        })
+        .collect())
 }
 
-fn topological_sort_helper(
-    current_target: Target,
-    output: &mut Vec<TargetFiles>,
-    visited: &mut Visited,
-    temp_visited: &mut Visited,
+/// Finds every target defined in a build file at or below the directory of a
+/// recursive target. Build files are visited in path order so the expansion
+/// is deterministic.
+fn expand_recursive_target(
+    target: &Target,
     root: &VfsPath,
-) -> Result<(), DependencySortError> {
-    let target_string = current_target.to_string();
-    if visited.contains(&target_string) {
-        return Ok(());
+    packages: &mut PackageCache,
+) -> Result<Vec<Target>, DependencySortError> {
+    let mut targets = Vec::new();
+    for (package, build_file_path) in find_packages(target, root)? {
+        let parsed = packages.package_at(&package, &build_file_path)?;
+        targets.extend(package_targets(&package, &parsed)?);
     }
+    Ok(targets)
+}
+
+/// Every target defined in `build_file`, the build file of `package`.
+fn package_targets(
+    package: &str,
+    build_file: &BuildFile,
+) -> Result<Vec<Target>, DependencySortError> {
+    build_file
+        .target_names()
+        .iter()
+        .map(|name| {
+            parse_target(&format!("{package}:{name}"))
+                .map_err(DependencySortError::ParseTargetError)
+        })
+        .collect()
+}
+
+/// Expands a target pattern into the specific targets it matches. Specific
+/// targets match only themselves.
+pub fn expand_target_pattern(
+    target: &Target,
+    root: &VfsPath,
+) -> Result<Vec<Target>, DependencySortError> {
+    expand_pattern(target, root, &mut PackageCache::new())
+}
 
-    if temp_visited.contains(&target_string) {
-        return Err(DependencySortError::CyclicDependency(current_target));
+pub(crate) fn expand_pattern(
+    target: &Target,
+    root: &VfsPath,
+    packages: &mut PackageCache,
+) -> Result<Vec<Target>, DependencySortError> {
+    if target.is_recursive() {
+        expand_recursive_target(target, root, packages)
+    } else {
+        Ok(Vec::from([target.clone()]))
     }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VisitState {
+    Unvisited,
+    InProgress,
+    Visited,
+}
 
-    temp_visited.insert(target_string.clone());
+/// A target whose dependencies are being visited.
+struct Frame {
+    id: TargetId,
+    definition: TargetDefinition,
+    /// Source files of the target, with globs expanded.
+    files: Vec<String>,
+    /// Index of the next entry of `definition.dependencies()` to expand.
+    next_dependency: usize,
+    /// Targets the current dependency expanded to that are still to be
+    /// visited, last one first.
+    pending: Vec<Target>,
+    dependency_targets: Vec<Target>,
+}
 
-    let build_file = resolve_build_file(&current_target, root)?;
-    let mut current_library: Option<&Library> = None;
+/// Depth first search over the dependency graph. Uses an explicit stack
+/// rather than recursion so deep dependency chains cannot overflow the call
+/// stack.
+struct Sorter<'a> {
+    root: &'a VfsPath,
+    packages: PackageCache,
+    interner: Interner,
+    /// Indexed by `TargetId`.
+    states: Vec<VisitState>,
+    /// Indexed by `TargetId`, `None` until the target's definition is found.
+    kinds: Vec<Option<TargetKind>>,
+    /// Indexed by `TargetId`, the resolved visibility rules of targets that
+    /// restrict who may depend on them.
+    visibilities: Vec<Option<Vec<Target>>>,
+    /// Every target currently being visited, outermost first.
+    stack: Vec<Frame>,
+    output: Vec<TargetFiles>,
+    /// `Some` when errors are collected instead of stopping the sort.
+    errors: Option<Vec<DependencySortError>>,
+}
 
-    let target_name_string = current_target.name();
-    if let Some(libraries) = &build_file.library {
-        for library in libraries {
-            if library.name == target_name_string {
-                current_library = Some(library);
+impl<'a> Sorter<'a> {
+    fn new(root: &'a VfsPath, collect_errors: bool) -> Self {
+        Self {
+            root,
+            packages: PackageCache::new(),
+            interner: Interner::new(),
+            states: Vec::new(),
+            kinds: Vec::new(),
+            visibilities: Vec::new(),
+            stack: Vec::new(),
+            output: Vec::new(),
+            errors: collect_errors.then(Vec::new),
+        }
+    }
 
-                if let Some(dependencies) = &library.dependencies {
-                    for dep in dependencies {
-                        let dep_target =
-                            parse_target(dep).map_err(DependencySortError::ParseTargetError)?;
+    /// Stops the sort with `error`, or records it and carries on when
+    /// collecting errors.
+    fn record(&mut self, error: DependencySortError) -> Result<(), DependencySortError> {
+        match &mut self.errors {
+            Some(errors) => {
+                errors.push(error);
+                Ok(())
+            }
+            None => Err(error),
+        }
+    }
 
-                        topological_sort_helper(dep_target, output, visited, temp_visited, root)?;
-                    }
-                }
+    fn intern(&mut self, target: &Target) -> TargetId {
+        let id = self.interner.intern(target);
+        if id.index() == self.states.len() {
+            self.states.push(VisitState::Unvisited);
+            self.kinds.push(None);
+            self.visibilities.push(None);
+        }
+        id
+    }
 
-                break;
+    /// Expands `pattern` into the targets it matches. Unlike `expand_pattern`,
+    /// a package whose build file does not parse is recorded and skipped when
+    /// collecting errors, so the rest of the packages are still visited.
+    fn expand(&mut self, pattern: &Target) -> Result<Vec<Target>, DependencySortError> {
+        if !pattern.is_recursive() {
+            return Ok(Vec::from([pattern.clone()]));
+        }
+        let mut targets = Vec::new();
+        for (package, build_file_path) in find_packages(pattern, self.root)? {
+            match self.packages.package_at(&package, &build_file_path) {
+                Ok(parsed) => targets.extend(package_targets(&package, &parsed)?),
+                Err(error) => self.record(error)?,
             }
         }
+        Ok(targets)
     }
 
-    match current_library {
-        Some(library) => {
-            temp_visited.remove(&target_string);
-            visited.insert(target_string);
-            let target_files = TargetFiles {
-                target: current_target,
-                files: library.files.clone().unwrap_or_default(),
+    /// Visits every target matched by `head_targets` that is not matched by
+    /// `excluded`.
+    fn visit_heads(
+        &mut self,
+        head_targets: &[Target],
+        excluded: &[Target],
+    ) -> Result<(), DependencySortError> {
+        for head_target in head_targets {
+            let targets = match self.expand(head_target) {
+                Ok(targets) => targets,
+                Err(error) => {
+                    self.record(error)?;
+                    continue;
+                }
             };
-            output.push(target_files);
-            Ok(())
+            for target in targets {
+                if !excluded.iter().any(|pattern| pattern.matches(&target)) {
+                    self.visit(target)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Visits `target` and everything it depends on, adding each to the
+    /// output after all of its dependencies.
+    fn visit(&mut self, target: Target) -> Result<(), DependencySortError> {
+        self.enter(target)?;
+        while let Some(frame) = self.stack.last_mut() {
+            if let Some(target) = frame.pending.pop() {
+                frame.dependency_targets.push(target.clone());
+                self.enter(target)?;
+            } else if let Some(dependency) = frame
+                .definition
+                .dependencies()
+                .get(frame.next_dependency)
+                .cloned()
+            {
+                frame.next_dependency += 1;
+                self.expand_dependency(&dependency)?;
+            } else {
+                let frame = self.stack.pop().unwrap();
+                self.states[frame.id.index()] = VisitState::Visited;
+                self.output.push(TargetFiles {
+                    target: self.interner.target(frame.id).clone(),
+                    files: frame.files,
+                    dependencies: frame.dependency_targets,
+                    definition: frame.definition,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts visiting `target`, unless it has already been visited.
+    fn enter(&mut self, target: Target) -> Result<(), DependencySortError> {
+        let id = self.intern(&target);
+        let state = self.states[id.index()];
+        let mut definition = None;
+        if state == VisitState::Unvisited {
+            match self.find_definition(&target) {
+                Ok(found) => {
+                    self.kinds[id.index()] = Some(found.0.kind());
+                    self.visibilities[id.index()] = found.0.visibility().map(|rules| {
+                        rules
+                            .iter()
+                            .map(|rule| rule.in_package(target.get_directories()))
+                            .collect()
+                    });
+                    definition = Some(found);
+                }
+                Err(error) => {
+                    // A target that failed is not visited again, so each
+                    // problem is only reported once when collecting errors.
+                    self.states[id.index()] = VisitState::Visited;
+                    return self.record(error);
+                }
+            }
+        }
+
+        // Every edge is checked, even to targets that were already visited.
+        // A bad edge does not stop the target from being visited, so errors
+        // further down the graph are still found when collecting errors.
+        if let (Some(dependent), Some(kind)) = (self.stack.last(), self.kinds[id.index()]) {
+            let dependent_kind = dependent.definition.kind();
+            if !dependent_kind.can_depend_on(kind) {
+                let dependent = self.interner.target(dependent.id).clone();
+                self.record(DependencySortError::ForbiddenDependency(
+                    dependent,
+                    dependent_kind,
+                    target.clone(),
+                    kind,
+                ))?;
+            }
+        }
+        if let (Some(dependent), Some(visibility)) =
+            (self.stack.last(), &self.visibilities[id.index()])
+        {
+            // Targets can always depend on targets in their own package.
+            let dependent = self.interner.target(dependent.id);
+            if dependent.get_directories() != target.get_directories()
+                && !visibility
+                    .iter()
+                    .any(|rule| visibility_allows(rule, dependent))
+            {
+                let error = DependencySortError::NotVisible(
+                    dependent.clone(),
+                    target.clone(),
+                    visibility.clone(),
+                );
+                self.record(error)?;
+            }
+        }
+
+        match state {
+            VisitState::Visited => return Ok(()),
+            VisitState::InProgress => {
+                // The stack holds every target currently being visited, so the
+                // cycle is everything from the earlier visit of this target
+                // onwards.
+                let start = self
+                    .stack
+                    .iter()
+                    .position(|frame| frame.id == id)
+                    .unwrap_or_default();
+                let mut cycle = self.stack[start..]
+                    .iter()
+                    .map(|frame| self.interner.target(frame.id).clone())
+                    .collect::<Vec<Target>>();
+                cycle.push(target);
+                return self.record(DependencySortError::CyclicDependency(cycle));
+            }
+            VisitState::Unvisited => {}
+        }
+
+        self.states[id.index()] = VisitState::InProgress;
+        let (definition, files) = definition.unwrap();
+        self.stack.push(Frame {
+            id,
+            definition,
+            files,
+            next_dependency: 0,
+            pending: Vec::new(),
+            dependency_targets: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Finds the definition of `target` and its source files.
+    fn find_definition(
+        &mut self,
+        target: &Target,
+    ) -> Result<(TargetDefinition, Vec<String>), DependencySortError> {
+        let build_file = self.packages.package_of(target, self.root)?;
+        let Some(definition) = build_file.target(target.name()) else {
+            return Err(DependencySortError::TargetNotFoundInBuildFile(
+                target.clone(),
+                suggest_targets(target, self.root),
+            ));
+        };
+        let package = self
+            .root
+            .join(target.get_directories())
+            .map_err(DependencySortError::VfsError)?;
+        let files = expand_source_files(&mut self.packages, &package, definition.files()).map_err(
+            |error| match error {
+                SourceFilesError::InvalidPattern(pattern, error) => {
+                    DependencySortError::InvalidFilePattern(target.clone(), pattern, error)
+                }
+                SourceFilesError::VfsError(error) => DependencySortError::VfsError(error),
+            },
+        )?;
+        definition
+            .validate(&build_file_location(target), &files)
+            .map_err(|diagnostic| DependencySortError::InvalidDefinition(Box::new(diagnostic)))?;
+        Ok((definition, files))
+    }
+
+    /// Expands one dependency of the innermost target into the targets it
+    /// still has to visit.
+    fn expand_dependency(&mut self, dependency: &Target) -> Result<(), DependencySortError> {
+        let frame = self.stack.last().unwrap();
+        // Dependencies are relative to the package that declares them.
+        let package = self.interner.target(frame.id).get_directories();
+        let dependency_target = dependency.in_package(package);
+        match self.expand(&dependency_target) {
+            Ok(mut targets) => {
+                targets.reverse();
+                self.stack.last_mut().unwrap().pending = targets;
+                Ok(())
+            }
+            Err(error) => self.record(error),
         }
-        None => Err(DependencySortError::TargetNotFoundInBuildFile(
-            current_target,
-        )),
     }
 }
 
-// TODO: support recursive targets
 pub fn topologically_sort_dep_graph(
     head_target: Target,
     root: &VfsPath,
 ) -> Result<Vec<TargetFiles>, DependencySortError> {
-    let mut output = Vec::new();
-    let mut visited: Visited = Visited::new();
-    let mut temp_visited: Visited = Visited::new();
-
-    topological_sort_helper(
-        head_target,
-        &mut output,
-        &mut visited,
-        &mut temp_visited,
-        root,
-    )?;
-
-    Ok(output)
+    topologically_sort_dep_graphs(&[head_target], &[], root)
+}
+
+/// Resolves every target matched by `head_targets` into a single build order
+/// in which shared dependencies appear once. Targets matched by `excluded`
+/// are left out of the heads, but are still resolved if an included target
+/// depends on them.
+pub fn topologically_sort_dep_graphs(
+    head_targets: &[Target],
+    excluded: &[Target],
+    root: &VfsPath,
+) -> Result<Vec<TargetFiles>, DependencySortError> {
+    let mut sorter = Sorter::new(root, false);
+    sorter.visit_heads(head_targets, excluded)?;
+    Ok(sorter.output)
+}
+
+/// Walks the whole dependency graph of `head_target` without stopping at the
+/// first problem, and returns every error found sorted by the file it is in.
+pub fn check_dep_graph(head_target: Target, root: &VfsPath) -> Vec<DependencySortError> {
+    let mut sorter = Sorter::new(root, true);
+    // Errors are always recorded rather than returned while collecting.
+    let _ = sorter.visit_heads(&[head_target], &[]);
+    let mut errors = sorter.errors.unwrap_or_default();
+    // Errors without a file sort last.
+    errors.sort_by_key(|error| (error.file().is_none(), error.file()));
+    errors
 }
 
 #[cfg(test)]
@@ -118,7 +558,10 @@ mod test {
         let root: VfsPath = MemoryFS::new().into();
         let target = parse_target("foo:bar").unwrap();
         let result = topologically_sort_dep_graph(target, &root);
-        assert!(matches!(result, Err(DependencySortError::VfsError(_))));
+        assert!(matches!(
+            result,
+            Err(DependencySortError::BuildFileNotFound(_, _))
+        ));
     }
 
     #[test]
@@ -130,11 +573,12 @@ mod test {
         println!("{:?}", result);
         assert!(matches!(
             result,
-            Err(DependencySortError::TargetNotFoundInBuildFile(_))
+            Err(DependencySortError::TargetNotFoundInBuildFile(_, _))
         ));
     }
 
     #[test]
+    #[allow(clippy::redundant_pattern_matching)]
     fn only_sorted_one_target_if_target_has_no_dependencies() {
         let root: VfsPath = MemoryFS::new().into();
         let target = parse_target("foo:bar").unwrap();
@@ -163,11 +607,44 @@ mod test {
             b"
             [[library]]
             name = \"bar\"
-            dependencies = [\"baz:qux\"]
+            dependencies = [\"//baz:qux\"]
             ",
         );
         let result = topologically_sort_dep_graph(target, &root);
-        assert!(matches!(result, Err(DependencySortError::VfsError(_))));
+        assert!(matches!(
+            result,
+            Err(DependencySortError::BuildFileNotFound(_, _))
+        ));
+    }
+
+    #[test]
+    fn suggests_similar_targets_for_typos_in_dependencies() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"bar\"
+            dependencies = [\"//foo:bza\"]
+
+            [[library]]
+            name = \"baz\"
+            ",
+        );
+        let result = topologically_sort_dep_graph(parse_target("foo:bar").unwrap(), &root);
+        let Err(DependencySortError::TargetNotFoundInBuildFile(target, suggestions)) = result
+        else {
+            panic!("expected a missing target, got {result:?}");
+        };
+        assert_eq!(target, parse_target("foo:bza").unwrap());
+        assert_eq!(
+            suggestions,
+            Vec::from([
+                parse_target("foo:baz").unwrap(),
+                parse_target("foo:bar").unwrap()
+            ])
+        );
     }
 
     #[test]
@@ -180,14 +657,14 @@ mod test {
             b"
             [[library]]
             name = \"bar\"
-            dependencies = [\"fizz:buzz\"]
+            dependencies = [\"//fizz:buzz\"]
             ",
         );
         create_test_file(&root, "fizz/BUILD.toml", b"");
         let result = topologically_sort_dep_graph(target, &root);
         assert!(matches!(
             result,
-            Err(DependencySortError::TargetNotFoundInBuildFile(_))
+            Err(DependencySortError::TargetNotFoundInBuildFile(_, _))
         ));
     }
 
@@ -201,7 +678,7 @@ mod test {
             b"
             [[library]]
             name = \"bar\"
-            dependencies = [\"fizz:buzz\"]
+            dependencies = [\"//fizz:buzz\"]
             ",
         );
         create_test_file(
@@ -226,7 +703,7 @@ mod test {
             b"
             [[library]]
             name = \"bar\"
-            dependencies = [\"fizz/buzz:qux\", \"hello:world\"]
+            dependencies = [\"//fizz/buzz:qux\", \"//hello:world\"]
             ",
         );
         create_test_file(
@@ -259,7 +736,7 @@ mod test {
             b"
             [[library]]
             name = \"bar\"
-            dependencies = [\"fizz/buzz:qux\", \"fizz/buzz:world\"]
+            dependencies = [\"//fizz/buzz:qux\", \"//fizz/buzz:world\"]
             ",
         );
         create_test_file(
@@ -287,15 +764,15 @@ mod test {
             b"
             [[library]]
             name = \"a\"
-            dependencies = [\"foo:b\", \"foo:c\"]
+            dependencies = [\"//foo:b\", \"//foo:c\"]
 
             [[library]]
             name = \"b\"
-            dependencies = [\"foo:d\"]
+            dependencies = [\"//foo:d\"]
 
             [[library]]
             name = \"c\"
-            dependencies = [\"foo:d\"]
+            dependencies = [\"//foo:d\"]
 
             [[library]]
             name = \"d\"
@@ -316,11 +793,11 @@ mod test {
             b"
             [[library]]
             name = \"a\"
-            dependencies = [\"foo:b\"]
+            dependencies = [\"//foo:b\"]
 
             [[library]]
             name = \"b\"
-            dependencies = [\"foo:a\"]
+            dependencies = [\"//foo:a\"]
             ",
         );
         let result = topologically_sort_dep_graph(target, &root);
@@ -330,6 +807,500 @@ mod test {
         ));
     }
 
+    #[test]
+    fn reports_where_a_build_file_is_malformed() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"[[library]]\nname = \"bar\"\nfile = []\n",
+        );
+        let error =
+            topologically_sort_dep_graph(parse_target("foo:bar").unwrap(), &root).unwrap_err();
+        let DependencySortError::BuildFileParseError(diagnostic) = &error else {
+            panic!("expected a parse error, got {error:?}");
+        };
+        assert_eq!(diagnostic.path, "foo/BUILD.toml");
+        assert_eq!(diagnostic.location.as_ref().unwrap().line, 3);
+    }
+
+    #[test]
+    fn reports_where_an_invalid_dependency_is_written() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"[[library]]\nname = \"bar\"\ndependencies = [\"foo:b$d\"]\n",
+        );
+        let error =
+            topologically_sort_dep_graph(parse_target("foo:bar").unwrap(), &root).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "error: invalid target `foo:b$d`: target contains an illegal character `$`
+ --> foo/BUILD.toml:3:17
+  |
+3 | dependencies = [\"foo:b$d\"]
+  |                 ^^^^^^^^^
+  |
+  = hint: targets look like `//path/to/package:name` or `:name` within a package"
+        );
+    }
+
+    #[test]
+    fn check_collects_every_error_sorted_by_file() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//zed:b\", \"//bar:missing\", \"//foo:c\"]
+
+            [[library]]
+            name = \"c\"
+            dependencies = [\"//foo:a\"]
+            ",
+        );
+        create_test_file(
+            &root,
+            "bar/BUILD.toml",
+            b"
+            [[library]]
+            name = \"b\"
+            ",
+        );
+        let errors = check_dep_graph(parse_target("foo:a").unwrap(), &root);
+        let files = errors
+            .iter()
+            .map(|error| error.file().unwrap())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            files,
+            Vec::from(["bar/BUILD.toml", "foo/BUILD.toml", "zed/BUILD.toml"])
+        );
+        assert!(matches!(
+            errors[0],
+            DependencySortError::TargetNotFoundInBuildFile(_, _)
+        ));
+        assert!(matches!(
+            errors[1],
+            DependencySortError::CyclicDependency(_)
+        ));
+        assert!(matches!(
+            errors[2],
+            DependencySortError::BuildFileNotFound(_, _)
+        ));
+    }
+
+    #[test]
+    fn check_finds_nothing_in_a_valid_graph() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//foo:b\"]
+
+            [[library]]
+            name = \"b\"
+            ",
+        );
+        assert!(check_dep_graph(parse_target("foo:a").unwrap(), &root).is_empty());
+    }
+
+    #[test]
+    fn resolves_deep_dependency_chains() {
+        // Deep enough to overflow the stack of a test thread if every
+        // dependency edge were a recursive call.
+        let root: VfsPath = MemoryFS::new().into();
+        let packages = 100;
+        let libraries = 100;
+        for package in 0..packages {
+            let mut build_file = String::new();
+            for library in 0..libraries {
+                let next = match (library + 1 < libraries, package + 1 < packages) {
+                    (true, _) => format!("\":lib{}\"", library + 1),
+                    (false, true) => format!("\"//pkg{}:lib0\"", package + 1),
+                    (false, false) => String::new(),
+                };
+                build_file.push_str(&format!(
+                    "[[library]]\nname = \"lib{library}\"\ndependencies = [{next}]\n"
+                ));
+            }
+            create_test_file(
+                &root,
+                &format!("pkg{package}/BUILD.toml"),
+                build_file.as_bytes(),
+            );
+        }
+        let graph =
+            topologically_sort_dep_graph(parse_target("pkg0:lib0").unwrap(), &root).unwrap();
+        assert_eq!(graph.len(), packages * libraries);
+        assert_eq!(graph[0].target.to_string(), "pkg99:lib99");
+        assert_eq!(graph[graph.len() - 1].target.to_string(), "pkg0:lib0");
+    }
+
+    fn names(nodes: &[TargetFiles]) -> Vec<String> {
+        nodes.iter().map(|node| node.target.to_string()).collect()
+    }
+
+    fn app_workspace(library_dependencies: &str) -> VfsPath {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "app/BUILD.toml",
+            format!(
+                "
+                [[library]]
+                name = \"lib\"
+                files = [\"lib.buri\"]
+                dependencies = [{library_dependencies}]
+
+                [[binary]]
+                name = \"app\"
+                entry_point = \"main.buri\"
+                files = [\"main.buri\"]
+                dependencies = [\":lib\"]
+
+                [[test]]
+                name = \"app_test\"
+                files = [\"app_test.buri\"]
+                dependencies = [\":app\", \":lib\"]
+                "
+            )
+            .as_bytes(),
+        );
+        root
+    }
+
+    #[test]
+    fn sorts_binaries_and_tests() {
+        let root = app_workspace("");
+        let graph = topologically_sort_dep_graph(parse_target("app:...").unwrap(), &root).unwrap();
+        assert_eq!(
+            names(&graph),
+            Vec::from(["app:lib", "app:app", "app:app_test"])
+        );
+        assert_eq!(graph[1].definition.kind(), TargetKind::Binary);
+        assert_eq!(graph[1].files, Vec::from(["main.buri"]));
+        assert_eq!(graph[2].definition.kind(), TargetKind::Test);
+        assert_eq!(graph[2].files, Vec::from(["app_test.buri"]));
+    }
+
+    #[test]
+    fn errors_if_a_target_depends_on_a_kind_it_cannot() {
+        let root = app_workspace("\":app_test\"");
+        let error =
+            topologically_sort_dep_graph(parse_target("app:lib").unwrap(), &root).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "library app:lib cannot depend on test app:app_test"
+        );
+        assert_eq!(error.file().unwrap(), "app/BUILD.toml");
+
+        // Edges to targets that were already visited are checked too.
+        let root = app_workspace("\":app\"");
+        let errors = check_dep_graph(parse_target("app:app_test").unwrap(), &root);
+        assert_eq!(
+            errors
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<String>>(),
+            Vec::from([
+                "library app:lib cannot depend on binary app:app",
+                "Dependency cycle detected: app:app -> app:lib -> app:app",
+            ])
+        );
+    }
+
+    #[test]
+    fn errors_if_a_dependency_is_not_visible() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "libs/core/BUILD.toml",
+            b"
+            [[library]]
+            name = \"internal\"
+            visibility = [\"//apps:...\", \":__pkg__\"]
+
+            [[library]]
+            name = \"public\"
+            dependencies = [\":internal\"]
+            ",
+        );
+        create_test_file(
+            &root,
+            "apps/web/BUILD.toml",
+            b"
+            [[library]]
+            name = \"web\"
+            dependencies = [\"//libs/core:internal\"]
+            ",
+        );
+        create_test_file(
+            &root,
+            "tools/BUILD.toml",
+            b"
+            [[library]]
+            name = \"tool\"
+            dependencies = [\"//libs/core:public\", \"//libs/core:internal\"]
+            ",
+        );
+        assert!(check_dep_graph(parse_target("apps/web").unwrap(), &root).is_empty());
+
+        // The edge is checked even though the dependency was already visited.
+        let error =
+            topologically_sort_dep_graph(parse_target("tools:tool").unwrap(), &root).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "tools:tool cannot depend on libs/core:internal, \
+             which is only visible to //apps:..., //libs/core:__pkg__"
+        );
+        assert_eq!(error.file().unwrap(), "tools/BUILD.toml");
+    }
+
+    #[test]
+    fn check_keeps_expanding_past_broken_build_files() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(&root, "a/BUILD.toml", b"[[library]]\nname = 1\n");
+        create_test_file(
+            &root,
+            "b/BUILD.toml",
+            b"
+            [[library]]
+            name = \"b\"
+            dependencies = [\":missing\"]
+            ",
+        );
+        let errors = check_dep_graph(parse_target("...").unwrap(), &root);
+        assert_eq!(
+            errors
+                .iter()
+                .map(|error| error.file().unwrap())
+                .collect::<Vec<String>>(),
+            Vec::from(["a/BUILD.toml", "b/BUILD.toml"])
+        );
+        assert!(matches!(
+            errors[0],
+            DependencySortError::BuildFileParseError(_)
+        ));
+        assert!(matches!(
+            errors[1],
+            DependencySortError::TargetNotFoundInBuildFile(_, _)
+        ));
+    }
+
+    #[test]
+    fn check_walks_past_forbidden_dependencies() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\":b_test\", \":c\"]
+
+            [[test]]
+            name = \"b_test\"
+            files = [\"b_test.buri\"]
+            dependencies = [\"//bar:missing\"]
+
+            [[library]]
+            name = \"c\"
+            dependencies = [\":b_test\"]
+            ",
+        );
+        let errors = check_dep_graph(parse_target("foo:a").unwrap(), &root);
+        assert_eq!(
+            errors
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<String>>(),
+            Vec::from([
+                "Build file bar/BUILD.toml for target bar:missing does not exist",
+                "library foo:a cannot depend on test foo:b_test",
+                "library foo:c cannot depend on test foo:b_test",
+            ])
+        );
+    }
+
+    #[test]
+    fn errors_if_a_binary_entry_point_is_not_one_of_its_files() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "app/BUILD.toml",
+            b"
+            [[binary]]
+            name = \"app\"
+            entry_point = \"main.buri\"
+            files = [\"app.buri\"]
+            ",
+        );
+        let error =
+            topologically_sort_dep_graph(parse_target("app:app").unwrap(), &root).unwrap_err();
+        let DependencySortError::InvalidDefinition(diagnostic) = &error else {
+            panic!("expected an invalid definition, got {error:?}");
+        };
+        assert_eq!(diagnostic.path, "app/BUILD.toml");
+        assert_eq!(
+            diagnostic.message,
+            "entry point `main.buri` of binary `app` is not one of its files"
+        );
+    }
+
+    fn two_package_workspace() -> VfsPath {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "a/BUILD.toml",
+            b"
+            [[library]]
+            name = \"x\"
+            dependencies = [\"//shared:s\"]
+            ",
+        );
+        create_test_file(
+            &root,
+            "b/BUILD.toml",
+            b"
+            [[library]]
+            name = \"y\"
+            dependencies = [\"//shared:s\"]
+            ",
+        );
+        create_test_file(
+            &root,
+            "shared/BUILD.toml",
+            b"
+            [[library]]
+            name = \"s\"
+            ",
+        );
+        create_test_file(
+            &root,
+            "shared/legacy/BUILD.toml",
+            b"
+            [[library]]
+            name = \"old\"
+            ",
+        );
+        root
+    }
+
+    #[test]
+    fn merges_multiple_heads_into_one_order() {
+        let heads = [parse_target("a:x").unwrap(), parse_target("b:y").unwrap()];
+        let graph = topologically_sort_dep_graphs(&heads, &[], &two_package_workspace()).unwrap();
+        assert_eq!(names(&graph), Vec::from(["shared:s", "a:x", "b:y"]));
+    }
+
+    #[test]
+    fn excludes_targets_matched_by_exclusion_patterns() {
+        let heads = [parse_target("...").unwrap()];
+        let excluded = [
+            parse_target("shared/legacy:...").unwrap(),
+            parse_target("b:y").unwrap(),
+        ];
+        let graph =
+            topologically_sort_dep_graphs(&heads, &excluded, &two_package_workspace()).unwrap();
+        assert_eq!(names(&graph), Vec::from(["shared:s", "a:x"]));
+    }
+
+    #[test]
+    fn keeps_excluded_targets_that_are_dependencies() {
+        let heads = [parse_target("a:x").unwrap()];
+        let excluded = [parse_target("shared:...").unwrap()];
+        let graph =
+            topologically_sort_dep_graphs(&heads, &excluded, &two_package_workspace()).unwrap();
+        assert_eq!(names(&graph), Vec::from(["shared:s", "a:x"]));
+    }
+
+    #[test]
+    fn resolves_dependencies_relative_to_their_package() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\":sibling\", \"bar:nested\", \"//baz:absolute\"]
+
+            [[library]]
+            name = \"sibling\"
+            ",
+        );
+        create_test_file(
+            &root,
+            "foo/bar/BUILD.toml",
+            b"
+            [[library]]
+            name = \"nested\"
+            ",
+        );
+        create_test_file(
+            &root,
+            "baz/BUILD.toml",
+            b"
+            [[library]]
+            name = \"absolute\"
+            ",
+        );
+        let graph = topologically_sort_dep_graph(parse_target("foo:a").unwrap(), &root).unwrap();
+        assert_eq!(
+            names(&graph),
+            Vec::from(["foo:sibling", "foo/bar:nested", "baz:absolute", "foo:a"])
+        );
+    }
+
+    #[test]
+    fn cycle_error_contains_the_whole_cycle() {
+        let root: VfsPath = MemoryFS::new().into();
+        let target = parse_target("foo:a").unwrap();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//foo:b\"]
+
+            [[library]]
+            name = \"b\"
+            dependencies = [\"//bar:c\"]
+            ",
+        );
+        create_test_file(
+            &root,
+            "bar/BUILD.toml",
+            b"
+            [[library]]
+            name = \"c\"
+            dependencies = [\"//foo:b\"]
+            ",
+        );
+        let error = topologically_sort_dep_graph(target, &root).unwrap_err();
+        let DependencySortError::CyclicDependency(cycle) = &error else {
+            panic!("expected a cycle, got {error:?}");
+        };
+        let cycle = cycle
+            .iter()
+            .map(|target| target.to_string())
+            .collect::<Vec<String>>();
+        // foo:a leads into the cycle but is not part of it.
+        assert_eq!(cycle, Vec::from(["foo:b", "bar:c", "foo:b"]));
+        assert_eq!(
+            error.to_string(),
+            "Dependency cycle detected: foo:b -> bar:c -> foo:b"
+        );
+    }
+
     #[test]
     fn topologically_sorts_dependencies() {
         let root: VfsPath = MemoryFS::new().into();
@@ -340,15 +1311,15 @@ mod test {
             b"
             [[library]]
             name = \"a\"
-            dependencies = [\"foo:b\", \"foo:c\"]
+            dependencies = [\"//foo:b\", \"//foo:c\"]
 
             [[library]]
             name = \"b\"
-            dependencies = [\"foo:d\"]
+            dependencies = [\"//foo:d\"]
 
             [[library]]
             name = \"c\"
-            dependencies = [\"foo:d\"]
+            dependencies = [\"//foo:d\"]
 
             [[library]]
             name = \"d\"
@@ -376,7 +1347,7 @@ mod test {
             b"
             [[library]]
             name = \"a\"
-            dependencies = [\"foo:b\"]
+            dependencies = [\"//foo:b\"]
             files = [\"a.buri\"]
 
             [[library]]
@@ -390,4 +1361,246 @@ mod test {
         assert_eq!(graph[0].files, Vec::from(["b.buri"]));
         assert_eq!(graph[1].files, Vec::from(["a.buri"]));
     }
+
+    #[test]
+    fn output_expands_file_globs() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[binary]]
+            name = \"app\"
+            entry_point = \"src/main.buri\"
+            files = [\"src/**/*.buri\", \"!src/**/*_test.buri\"]
+            ",
+        );
+        for file in [
+            "foo/src/main.buri",
+            "foo/src/main_test.buri",
+            "foo/src/lib/util.buri",
+            "foo/src/vendored/BUILD.toml",
+            "foo/src/vendored/other.buri",
+        ] {
+            create_test_file(&root, file, b"");
+        }
+        let graph = topologically_sort_dep_graph(parse_target("foo:app").unwrap(), &root).unwrap();
+        assert_eq!(
+            graph[0].files,
+            Vec::from(["src/lib/util.buri", "src/main.buri"])
+        );
+    }
+
+    #[test]
+    fn errors_on_invalid_file_globs() {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            files = [\"src/[a.buri\"]
+            ",
+        );
+        let error =
+            topologically_sort_dep_graph(parse_target("foo:a").unwrap(), &root).unwrap_err();
+        assert!(matches!(
+            &error,
+            DependencySortError::InvalidFilePattern(_, pattern, _) if pattern == "src/[a.buri"
+        ));
+        assert_eq!(error.file(), Some("foo/BUILD.toml".to_string()));
+    }
+
+    #[test]
+    fn output_tracks_direct_dependencies() {
+        let root: VfsPath = MemoryFS::new().into();
+        let target = parse_target("foo:a").unwrap();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//foo:b\", \"//bar:...\"]
+
+            [[library]]
+            name = \"b\"
+            dependencies = [\"//bar:c\"]
+            ",
+        );
+        create_test_file(
+            &root,
+            "bar/BUILD.toml",
+            b"
+            [[library]]
+            name = \"c\"
+            ",
+        );
+        let graph = topologically_sort_dep_graph(target, &root).unwrap();
+        let dependencies = graph
+            .iter()
+            .map(|target_files| {
+                target_files
+                    .dependencies
+                    .iter()
+                    .map(|dependency| dependency.to_string())
+                    .collect::<Vec<String>>()
+            })
+            .collect::<Vec<Vec<String>>>();
+        assert_eq!(
+            dependencies,
+            Vec::from([
+                Vec::new(),
+                Vec::from(["bar:c".to_string()]),
+                Vec::from(["foo:b".to_string(), "bar:c".to_string()]),
+            ])
+        );
+    }
+
+    #[test]
+    fn recursive_target_includes_every_library_below_directory() {
+        let root: VfsPath = MemoryFS::new().into();
+        let target = parse_target("foo:...").unwrap();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+
+            [[library]]
+            name = \"b\"
+            ",
+        );
+        create_test_file(
+            &root,
+            "foo/bar/baz/BUILD.toml",
+            b"
+            [[library]]
+            name = \"c\"
+            ",
+        );
+        create_test_file(
+            &root,
+            "qux/BUILD.toml",
+            b"
+            [[library]]
+            name = \"d\"
+            ",
+        );
+        let graph = topologically_sort_dep_graph(target, &root).unwrap();
+        let targets = graph
+            .iter()
+            .map(|target_files| target_files.target.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(targets, Vec::from(["foo:a", "foo:b", "foo/bar/baz:c"]));
+    }
+
+    #[test]
+    fn recursive_target_at_root_includes_every_build_file() {
+        let root: VfsPath = MemoryFS::new().into();
+        let target = parse_target("...").unwrap();
+        create_test_file(
+            &root,
+            "BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            ",
+        );
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"b\"
+            ",
+        );
+        create_test_file(
+            &root,
+            "foo/bar/BUILD.toml",
+            b"
+            [[library]]
+            name = \"c\"
+            ",
+        );
+        let graph = topologically_sort_dep_graph(target, &root).unwrap();
+        assert_eq!(graph.len(), 3);
+    }
+
+    #[test]
+    fn recursive_target_sorts_dependencies_and_dedupes() {
+        let root: VfsPath = MemoryFS::new().into();
+        let target = parse_target("foo:...").unwrap();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//foo/bar:b\", \"//shared:c\"]
+            ",
+        );
+        create_test_file(
+            &root,
+            "foo/bar/BUILD.toml",
+            b"
+            [[library]]
+            name = \"b\"
+            dependencies = [\"//shared:c\"]
+            ",
+        );
+        create_test_file(
+            &root,
+            "shared/BUILD.toml",
+            b"
+            [[library]]
+            name = \"c\"
+            ",
+        );
+        let graph = topologically_sort_dep_graph(target, &root).unwrap();
+        let targets = graph
+            .iter()
+            .map(|target_files| target_files.target.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(targets, Vec::from(["shared:c", "foo/bar:b", "foo:a"]));
+    }
+
+    #[test]
+    fn recursive_dependency_expands_into_graph() {
+        let root: VfsPath = MemoryFS::new().into();
+        let target = parse_target("foo:a").unwrap();
+        create_test_file(
+            &root,
+            "foo/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//bar:...\"]
+            ",
+        );
+        create_test_file(
+            &root,
+            "bar/BUILD.toml",
+            b"
+            [[library]]
+            name = \"b\"
+
+            [[library]]
+            name = \"c\"
+            ",
+        );
+        let graph = topologically_sort_dep_graph(target, &root).unwrap();
+        assert_eq!(graph.len(), 3);
+        assert_eq!(graph[2].target.to_string(), "foo:a");
+    }
+
+    #[test]
+    fn errors_if_recursive_target_directory_does_not_exist() {
+        let root: VfsPath = MemoryFS::new().into();
+        let target = parse_target("foo:...").unwrap();
+        let result = topologically_sort_dep_graph(target, &root);
+        assert!(matches!(result, Err(DependencySortError::VfsError(_))));
+    }
 }
@@ -1,22 +1,478 @@
+use crate::diagnostic::Diagnostic;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use target::Target;
 
 // Do not change without supplying a migration script.
 // This will lead to incompatibilities between versions.
 pub const BUILD_FILE_NAME: &str = "BUILD.toml";
+/// Extension of Buri source files.
+pub const SOURCE_FILE_EXTENSION: &str = "buri";
+/// Target name of a visibility rule that allows every target in one package,
+/// as in `//libs/core:__pkg__`.
+pub const PACKAGE_VISIBILITY: &str = "__pkg__";
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct BuildFile {
     pub library: Option<Vec<Library>>,
+    pub binary: Option<Vec<Binary>>,
+    pub test: Option<Vec<Test>>,
 }
 
-#[derive(Deserialize, Serialize)]
+impl BuildFile {
+    /// The target called `name`, whatever its kind.
+    pub fn target(&self, name: &str) -> Option<TargetDefinition> {
+        // Only the target found is cloned.
+        let library = self
+            .library
+            .iter()
+            .flatten()
+            .find(|library| library.name == name);
+        let binary = || {
+            self.binary
+                .iter()
+                .flatten()
+                .find(|binary| binary.name == name)
+        };
+        let test = || self.test.iter().flatten().find(|test| test.name == name);
+        library
+            .cloned()
+            .map(TargetDefinition::Library)
+            .or_else(|| binary().cloned().map(TargetDefinition::Binary))
+            .or_else(|| test().cloned().map(TargetDefinition::Test))
+    }
+
+    /// Names of every target in this build file, libraries first, then
+    /// binaries, then tests.
+    pub fn target_names(&self) -> Vec<&str> {
+        let libraries = self.library.iter().flatten().map(|library| &library.name);
+        let binaries = self.binary.iter().flatten().map(|binary| &binary.name);
+        let tests = self.test.iter().flatten().map(|test| &test.name);
+        libraries
+            .chain(binaries)
+            .chain(tests)
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+fn resolve_labels(targets: &[Target], package: &str) -> Vec<Target> {
+    targets
+        .iter()
+        .map(|target| target.in_package(package))
+        .collect()
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Library {
     /// name of the library target
     pub name: String,
-    /// all source files in this library
+    /// all source files in this library, as file names or globs such as
+    /// `src/**/*.buri`; patterns starting with `!` leave out the files they match
     pub files: Option<Vec<String>>,
     /// any targets this library depends on (including external deps)
-    pub dependencies: Option<Vec<String>>,
-    /// targets that depend on this target
-    pub dependents: Option<Vec<String>>,
+    pub dependencies: Option<Vec<Target>>,
+    /// targets that depend on this target, checked by `buri check` and
+    /// updated by `buri fix`
+    pub dependents: Option<Vec<Target>>,
+    /// targets allowed to depend on this library, as targets, patterns such
+    /// as `//apps/...` or whole packages such as `//libs/core:__pkg__`;
+    /// everything may depend on it when left out
+    pub visibility: Option<Vec<Target>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Binary {
+    /// name of the binary target
+    pub name: String,
+    /// source file the program starts in, which must be one of `files`
+    pub entry_point: String,
+    /// name of the executable that is produced, defaults to `name`
+    pub executable: Option<String>,
+    /// all source files in this binary, as file names or globs such as
+    /// `src/**/*.buri`; patterns starting with `!` leave out the files they match
+    pub files: Option<Vec<String>>,
+    /// libraries this binary depends on
+    pub dependencies: Option<Vec<Target>>,
+}
+
+impl Binary {
+    pub fn executable(&self) -> &str {
+        self.executable.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Test {
+    /// name of the test target
+    pub name: String,
+    /// test sources, at least one is required
+    pub files: Vec<String>,
+    /// libraries and binaries under test
+    pub dependencies: Option<Vec<Target>>,
+}
+
+/// The kinds of targets a build file can define.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TargetKind {
+    Library,
+    Binary,
+    Test,
+}
+
+impl TargetKind {
+    pub const ALL: [TargetKind; 3] = [Self::Library, Self::Binary, Self::Test];
+
+    /// The name of the kind, which is also its table name in build files.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Library => "library",
+            Self::Binary => "binary",
+            Self::Test => "test",
+        }
+    }
+
+    /// Whether a target of this kind may depend on a target of kind
+    /// `dependency`. Libraries and binaries may only depend on libraries,
+    /// tests may also depend on the binaries they run, and nothing may depend
+    /// on a test.
+    pub fn can_depend_on(self, dependency: TargetKind) -> bool {
+        match dependency {
+            Self::Library => true,
+            Self::Binary => self == Self::Test,
+            Self::Test => false,
+        }
+    }
+}
+
+impl fmt::Display for TargetKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A target of any kind, as written in its build file.
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub enum TargetDefinition {
+    Library(Library),
+    Binary(Binary),
+    Test(Test),
+}
+
+impl TargetDefinition {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Library(library) => &library.name,
+            Self::Binary(binary) => &binary.name,
+            Self::Test(test) => &test.name,
+        }
+    }
+
+    pub fn kind(&self) -> TargetKind {
+        match self {
+            Self::Library(_) => TargetKind::Library,
+            Self::Binary(_) => TargetKind::Binary,
+            Self::Test(_) => TargetKind::Test,
+        }
+    }
+
+    /// Source files, relative to the package.
+    pub fn files(&self) -> &[String] {
+        match self {
+            Self::Library(library) => library.files.as_deref().unwrap_or_default(),
+            Self::Binary(binary) => binary.files.as_deref().unwrap_or_default(),
+            Self::Test(test) => &test.files,
+        }
+    }
+
+    /// Dependencies as written, relative to the package unless absolute.
+    pub fn dependencies(&self) -> &[Target] {
+        let dependencies = match self {
+            Self::Library(library) => &library.dependencies,
+            Self::Binary(binary) => &binary.dependencies,
+            Self::Test(test) => &test.dependencies,
+        };
+        dependencies.as_deref().unwrap_or_default()
+    }
+
+    /// Visibility rules as written, if the target restricts who may depend
+    /// on it. Only libraries can.
+    pub fn visibility(&self) -> Option<&[Target]> {
+        match self {
+            Self::Library(library) => library.visibility.as_deref(),
+            Self::Binary(_) | Self::Test(_) => None,
+        }
+    }
+
+    /// Checks the rules specific to each kind of target. `path` is the build
+    /// file the target is defined in and `files` its source files once any
+    /// globs in `files()` are expanded.
+    pub fn validate(&self, path: &str, files: &[String]) -> Result<(), Diagnostic> {
+        match self {
+            Self::Library(_) => Ok(()),
+            Self::Binary(binary) => {
+                if files.contains(&binary.entry_point) {
+                    return Ok(());
+                }
+                let message = format!(
+                    "entry point `{}` of binary `{}` is not one of its files",
+                    binary.entry_point, binary.name
+                );
+                Err(Diagnostic::new(path, &message)
+                    .with_hint(&format!("add \"{}\" to `files`", binary.entry_point)))
+            }
+            Self::Test(test) => {
+                if !files.is_empty() {
+                    return Ok(());
+                }
+                let message = format!("test `{}` has no files", test.name);
+                Err(Diagnostic::new(path, &message).with_hint("list the test sources in `files`"))
+            }
+        }
+    }
+
+    /// This target with every label resolved against `package`, the directory
+    /// of its build file, so equivalent spellings of the same targets
+    /// serialize the same.
+    pub fn canonicalize(&self, package: &str) -> TargetDefinition {
+        let resolve = |targets: &Option<Vec<Target>>| {
+            targets
+                .as_ref()
+                .map(|targets| resolve_labels(targets, package))
+        };
+        match self {
+            Self::Library(library) => Self::Library(Library {
+                dependencies: resolve(&library.dependencies),
+                dependents: resolve(&library.dependents),
+                visibility: resolve(&library.visibility),
+                ..library.clone()
+            }),
+            Self::Binary(binary) => Self::Binary(Binary {
+                dependencies: resolve(&binary.dependencies),
+                ..binary.clone()
+            }),
+            Self::Test(test) => Self::Test(Test {
+                dependencies: resolve(&test.dependencies),
+                ..test.clone()
+            }),
+        }
+    }
+}
+
+/// Whether the resolved visibility rule `rule` lets `dependent` depend on the
+/// target the rule is on. `__pkg__` rules allow every target in their
+/// package, patterns allow the targets they match and anything else only
+/// allows itself.
+pub fn visibility_allows(rule: &Target, dependent: &Target) -> bool {
+    if rule.name() == PACKAGE_VISIBILITY {
+        rule.get_directories() == dependent.get_directories()
+    } else {
+        rule.matches(dependent)
+    }
+}
+
+/// Where the build file defining `target` is, relative to the workspace root.
+pub fn build_file_location(target: &Target) -> String {
+    let directories = target.get_directories();
+    if directories.is_empty() {
+        return BUILD_FILE_NAME.to_string();
+    }
+    format!("{directories}/{BUILD_FILE_NAME}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diagnostic::parse_toml;
+    use target::parse::parse_target;
+
+    #[test]
+    fn test_build_file_location() {
+        let tests = [
+            ["foo", "foo/BUILD.toml"],
+            ["foo:bar", "foo/BUILD.toml"],
+            ["foo/bar", "foo/bar/BUILD.toml"],
+            [":foo", "BUILD.toml"],
+        ];
+        for test in tests.iter() {
+            let target = parse_target(test[0]).unwrap();
+            assert_eq!(build_file_location(&target), test[1]);
+        }
+    }
+
+    #[test]
+    fn deserializes_dependencies_as_targets() {
+        let contents = "[[library]]\nname = \"a\"\ndependencies = [\"//foo:b\", \":c\"]\n";
+        let build_file = parse_toml::<BuildFile>("BUILD.toml", contents).unwrap();
+        let library = &build_file.library.unwrap()[0];
+        let dependencies = library.dependencies.as_ref().unwrap();
+        assert_eq!(dependencies[0], parse_target("foo:b").unwrap());
+        assert_eq!(dependencies[1].in_package("foo").to_string(), "foo:c");
+
+        // Labels are written back the way they were read.
+        let serialized = toml::to_string(library).unwrap();
+        assert!(serialized.contains(r#"dependencies = ["//foo:b", ":c"]"#));
+    }
+
+    #[test]
+    fn canonicalizes_labels() {
+        let read = |dependencies: &str| {
+            let contents = format!("[[library]]\nname = \"a\"\ndependencies = [{dependencies}]\n");
+            let build_file = parse_toml::<BuildFile>("foo/BUILD.toml", &contents).unwrap();
+            let definition = build_file.target("a").unwrap().canonicalize("foo");
+            toml::to_string(&definition).unwrap()
+        };
+        assert_eq!(
+            read(r#"":b", "bar""#),
+            read(r#""//foo:b", "//foo/bar:bar""#)
+        );
+    }
+
+    #[test]
+    fn finds_targets_of_every_kind() {
+        let contents = "
+            [[library]]
+            name = \"lib\"
+
+            [[binary]]
+            name = \"app\"
+            entry_point = \"main.buri\"
+            files = [\"main.buri\"]
+            dependencies = [\":lib\"]
+
+            [[test]]
+            name = \"app_test\"
+            files = [\"app_test.buri\"]
+            dependencies = [\":app\"]
+        ";
+        let build_file = parse_toml::<BuildFile>("BUILD.toml", contents).unwrap();
+        assert_eq!(
+            build_file.target_names(),
+            Vec::from(["lib", "app", "app_test"])
+        );
+        let binary = build_file.target("app").unwrap();
+        assert_eq!(binary.kind(), TargetKind::Binary);
+        assert_eq!(binary.files(), ["main.buri"]);
+        assert_eq!(binary.dependencies().len(), 1);
+        assert!(binary.validate("BUILD.toml", binary.files()).is_ok());
+        let TargetDefinition::Binary(binary) = binary else {
+            panic!("expected a binary");
+        };
+        assert_eq!(binary.executable(), "app");
+        assert_eq!(
+            build_file.target("app_test").unwrap().kind(),
+            TargetKind::Test
+        );
+        assert_eq!(build_file.target("missing"), None);
+    }
+
+    #[test]
+    fn validates_each_kind() {
+        let contents = "
+            [[binary]]
+            name = \"app\"
+            entry_point = \"main.buri\"
+            files = [\"lib.buri\"]
+
+            [[test]]
+            name = \"app_test\"
+            files = []
+        ";
+        let build_file = parse_toml::<BuildFile>("BUILD.toml", contents).unwrap();
+        let validate = |name| {
+            let definition = build_file.target(name).unwrap();
+            definition.validate("BUILD.toml", definition.files())
+        };
+        assert_eq!(
+            validate("app").unwrap_err().message,
+            "entry point `main.buri` of binary `app` is not one of its files"
+        );
+        assert_eq!(
+            validate("app_test").unwrap_err().message,
+            "test `app_test` has no files"
+        );
+    }
+
+    #[test]
+    fn requires_the_fields_of_each_kind() {
+        let diagnostic =
+            parse_toml::<BuildFile>("BUILD.toml", "[[binary]]\nname = \"app\"\n").unwrap_err();
+        assert!(diagnostic
+            .message
+            .starts_with("missing field `entry_point`"));
+        let diagnostic =
+            parse_toml::<BuildFile>("BUILD.toml", "[[test]]\nname = \"t\"\n").unwrap_err();
+        assert!(diagnostic.message.starts_with("missing field `files`"));
+    }
+
+    #[test]
+    fn only_libraries_and_binaries_can_be_depended_on() {
+        use TargetKind::*;
+        assert!(Library.can_depend_on(Library));
+        assert!(!Library.can_depend_on(Binary));
+        assert!(Binary.can_depend_on(Library));
+        assert!(!Binary.can_depend_on(Binary));
+        assert!(Test.can_depend_on(Binary));
+        for kind in TargetKind::ALL {
+            assert!(!kind.can_depend_on(Test));
+        }
+    }
+
+    #[test]
+    fn deserializes_visibility_rules() {
+        let contents =
+            "[[library]]\nname = \"a\"\nvisibility = [\"//apps/...\", \"//libs/core:__pkg__\"]\n";
+        let build_file = parse_toml::<BuildFile>("libs/core/BUILD.toml", contents).unwrap();
+        let definition = build_file.target("a").unwrap();
+        let apps = parse_target("apps/web:main").unwrap();
+        let core = parse_target("libs/core:b").unwrap();
+        let tools = parse_target("tools:tool").unwrap();
+        let allows = |dependent: &Target| {
+            definition
+                .visibility()
+                .unwrap()
+                .iter()
+                .any(|rule| visibility_allows(&rule.in_package("libs/core"), dependent))
+        };
+        assert!(allows(&apps));
+        assert!(allows(&core));
+        assert!(!allows(&tools));
+    }
+
+    #[test]
+    fn visibility_rules_allow_what_they_name() {
+        let tests = [
+            ["apps:...", "apps/web:main", "true"],
+            ["apps:...", "libs:main", "false"],
+            ["libs/core:__pkg__", "libs/core:a", "true"],
+            ["libs/core:__pkg__", "libs/core/sub:a", "false"],
+            ["libs:a", "libs:a", "true"],
+            ["libs:a", "libs:b", "false"],
+        ];
+        for test in tests.iter() {
+            let rule = parse_target(test[0]).unwrap();
+            let dependent = parse_target(test[1]).unwrap();
+            assert_eq!(
+                visibility_allows(&rule, &dependent).to_string(),
+                test[2],
+                "{test:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn points_at_invalid_dependencies() {
+        let contents = "[[library]]\nname = \"a\"\ndependencies = [\"//foo:b\", \"foo:b$d\"]\n";
+        let diagnostic = parse_toml::<BuildFile>("foo/BUILD.toml", contents).unwrap_err();
+        assert_eq!(
+            diagnostic.message,
+            "invalid target `foo:b$d`: target contains an illegal character `$`"
+        );
+        let location = diagnostic.location.unwrap();
+        assert_eq!((location.line, location.column), (3, 28));
+    }
 }
@@ -1,3 +1,4 @@
+use crate::diagnostic::{parse_toml, Diagnostic};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use version::{is_valid_version, normalize_version};
@@ -16,7 +17,7 @@ pub struct CliConfig {
 #[derive(Debug, Eq, PartialEq)]
 pub enum CliConfigParseError {
     InvalidVersion,
-    DeserializationError,
+    DeserializationError(Diagnostic),
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -48,8 +49,8 @@ impl CliConfig {
     }
 
     pub fn from(contents: &str) -> Result<Self, CliConfigParseError> {
-        let mut file = toml::from_str::<CliConfig>(contents)
-            .map_err(|_| CliConfigParseError::DeserializationError)?;
+        let mut file = parse_toml::<CliConfig>(CLI_CONFIG_FILE_NAME, contents)
+            .map_err(CliConfigParseError::DeserializationError)?;
         if let Some(version) = &file.buri_version {
             if !is_valid_version(version) {
                 return Err(CliConfigParseError::InvalidVersion);
@@ -0,0 +1,217 @@
+use serde::de::DeserializeOwned;
+use std::{fmt, ops::Range};
+
+/// Where in a file a diagnostic points.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Location {
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column, counted in characters
+    pub column: usize,
+    /// The full line the span starts on
+    pub snippet: String,
+    /// Number of characters to underline, never past the end of the line
+    pub length: usize,
+}
+
+impl Location {
+    pub fn from_span(contents: &str, span: Range<usize>) -> Self {
+        let start = span.start.min(contents.len());
+        let line_start = contents[..start].rfind('\n').map_or(0, |index| index + 1);
+        let line_end = contents[start..]
+            .find('\n')
+            .map_or(contents.len(), |index| start + index);
+        let end = span.end.clamp(start, line_end);
+        Self {
+            line: contents[..start].matches('\n').count() + 1,
+            column: contents[line_start..start].chars().count() + 1,
+            snippet: contents[line_start..line_end].trim_end().to_string(),
+            length: contents[start..end].chars().count().max(1),
+        }
+    }
+}
+
+/// An error in a file, rendered like a compiler error with the offending line
+/// and a caret under the problem.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub path: String,
+    pub message: String,
+    pub location: Option<Location>,
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(path: &str, message: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            message: message.to_string(),
+            location: None,
+            hint: None,
+        }
+    }
+
+    pub fn with_span(mut self, contents: &str, span: Range<usize>) -> Self {
+        self.location = Some(Location::from_span(contents, span));
+        self
+    }
+
+    pub fn with_hint(mut self, hint: &str) -> Self {
+        self.hint = Some(hint.to_string());
+        self
+    }
+
+    pub fn from_toml_error(path: &str, contents: &str, error: &toml::de::Error) -> Self {
+        // Syntax errors spread their message over several lines.
+        let message = error
+            .message()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        let mut diagnostic = Self::new(path, &message);
+        if let Some(span) = error.span() {
+            diagnostic = diagnostic.with_span(contents, span);
+        }
+        let hint = if message.starts_with("unknown field") {
+            Some("remove this key or fix its spelling")
+        } else if message.starts_with("invalid type") {
+            Some("change this value to the expected type")
+        } else if message.starts_with("missing field") {
+            Some("add the missing key to this table")
+        } else if message.starts_with("invalid target") {
+            Some("targets look like `//path/to/package:name` or `:name` within a package")
+        } else {
+            None
+        };
+        match hint {
+            Some(hint) => diagnostic.with_hint(hint),
+            None => diagnostic,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "error: {}", self.message)?;
+        let Some(location) = &self.location else {
+            write!(f, " --> {}", self.path)?;
+            if let Some(hint) = &self.hint {
+                write!(f, "\n  = hint: {hint}")?;
+            }
+            return Ok(());
+        };
+
+        let gutter = " ".repeat(location.line.to_string().len());
+        writeln!(
+            f,
+            "{gutter}--> {}:{}:{}",
+            self.path, location.line, location.column
+        )?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{} | {}", location.line, location.snippet)?;
+        write!(
+            f,
+            "{gutter} | {}{}",
+            " ".repeat(location.column - 1),
+            "^".repeat(location.length)
+        )?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n{gutter} |\n{gutter} = hint: {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses any of the TOML files in this crate, describing what is wrong with
+/// the file if it can't be parsed. `path` is only used for reporting.
+pub fn parse_toml<T: DeserializeOwned>(path: &str, contents: &str) -> Result<T, Diagnostic> {
+    toml::from_str(contents).map_err(|error| Diagnostic::from_toml_error(path, contents, &error))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{build_file::BuildFile, workspace_file::WorkspaceFile};
+
+    #[test]
+    fn locates_spans() {
+        let location = Location::from_span("a = 1\nbb = 2\n", 6..8);
+        assert_eq!(
+            location,
+            Location {
+                line: 2,
+                column: 1,
+                snippet: "bb = 2".to_string(),
+                length: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn underlines_at_least_one_character() {
+        let location = Location::from_span("a = ", 4..4);
+        assert_eq!(location.column, 5);
+        assert_eq!(location.length, 1);
+    }
+
+    #[test]
+    fn renders_like_a_compiler_error() {
+        let diagnostic = Diagnostic::new("foo/BUILD.toml", "something is wrong")
+            .with_span("[[library]]\nname = 3\n", 19..20)
+            .with_hint("fix it");
+        assert_eq!(
+            diagnostic.to_string(),
+            "error: something is wrong
+ --> foo/BUILD.toml:2:8
+  |
+2 | name = 3
+  |        ^
+  |
+  = hint: fix it"
+        );
+    }
+
+    #[test]
+    fn renders_without_a_location() {
+        let diagnostic = Diagnostic::new("WORKSPACE.toml", "something is wrong");
+        assert_eq!(
+            diagnostic.to_string(),
+            "error: something is wrong\n --> WORKSPACE.toml"
+        );
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        let diagnostic =
+            parse_toml::<BuildFile>("foo/BUILD.toml", "[[library]\nname = \"a\"\n").unwrap_err();
+        let location = diagnostic.location.unwrap();
+        assert_eq!((location.line, location.column), (1, 10));
+        assert_eq!(location.snippet, "[[library]");
+    }
+
+    #[test]
+    fn reports_unknown_keys() {
+        let contents = "[[library]]\nname = \"a\"\ndependecies = []\n";
+        let diagnostic = parse_toml::<BuildFile>("foo/BUILD.toml", contents).unwrap_err();
+        assert!(diagnostic
+            .message
+            .starts_with("unknown field `dependecies`"));
+        assert_eq!(
+            diagnostic.hint,
+            Some("remove this key or fix its spelling".to_string())
+        );
+        let location = diagnostic.location.unwrap();
+        assert_eq!((location.line, location.column), (3, 1));
+        assert_eq!(location.length, "dependecies".len());
+    }
+
+    #[test]
+    fn reports_wrong_types() {
+        let diagnostic = parse_toml::<WorkspaceFile>("WORKSPACE.toml", "name = 3\n").unwrap_err();
+        assert!(diagnostic.message.starts_with("invalid type"));
+        let location = diagnostic.location.unwrap();
+        assert_eq!((location.line, location.column), (1, 8));
+    }
+}
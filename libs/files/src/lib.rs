@@ -1,3 +1,4 @@
 pub mod build_file;
 pub mod cli_config;
+pub mod diagnostic;
 pub mod workspace_file;
@@ -1,10 +1,12 @@
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 
 // Do not change without supplying a migration script.
 // This will lead to incompatibilities between versions.
 pub const WORKSPACE_FILE_NAME: &str = "WORKSPACE.toml";
 
-#[derive(Deserialize, Serialize, Default)]
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
 pub struct WorkspaceFile {
     pub name: Option<String>,
 }
@@ -14,3 +16,43 @@ impl WorkspaceFile {
         Self { name: None }
     }
 }
+
+/// Environment variable the CLI uses to tell thor where the workspace root is.
+pub const WORKSPACE_ROOT_ENV_VAR: &str = "BURI_WORKSPACE_ROOT";
+/// Environment variable the CLI uses to tell thor which directory `buri` was
+/// run from, since the workspace root may be one of its ancestors.
+pub const WORKING_DIRECTORY_ENV_VAR: &str = "BURI_WORKING_DIRECTORY";
+
+/// Finds the workspace `directory` is in by walking up to the closest
+/// directory holding a `WORKSPACE.toml`.
+pub fn find_workspace_root(directory: &Path) -> Option<PathBuf> {
+    directory
+        .ancestors()
+        .find(|ancestor| ancestor.join(WORKSPACE_FILE_NAME).is_file())
+        .map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn finds_the_closest_workspace_root() {
+        let workspace =
+            std::env::temp_dir().join(format!("files-workspace-{}", std::process::id()));
+        let nested = workspace.join("foo").join("bar");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_workspace_root(&nested), None);
+
+        fs::write(workspace.join(WORKSPACE_FILE_NAME), "").unwrap();
+        assert_eq!(find_workspace_root(&nested), Some(workspace.clone()));
+        assert_eq!(find_workspace_root(&workspace), Some(workspace.clone()));
+
+        // A directory named like the workspace file does not count.
+        fs::create_dir_all(workspace.join("foo").join(WORKSPACE_FILE_NAME)).unwrap();
+        assert_eq!(find_workspace_root(&nested), Some(workspace.clone()));
+
+        fs::remove_dir_all(&workspace).unwrap();
+    }
+}
@@ -0,0 +1,434 @@
+use crate::parse::{parse_query, Expression, QueryParseError};
+use build_graph::{
+    expand_target_pattern, find_unowned_files, topologically_sort_dep_graph, BuildGraph,
+    DependencySortError, TargetFiles,
+};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap, VecDeque},
+    fmt,
+};
+use target::Target;
+use vfs::VfsPath;
+
+#[derive(Debug)]
+pub enum QueryError {
+    ParseError(QueryParseError),
+    DependencySortError(DependencySortError),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ParseError(error) => write!(f, "Invalid query: {error}"),
+            Self::DependencySortError(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+/// One result of a query. Most functions work on targets, but `unowned()`
+/// evaluates to source files.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryItem {
+    Target(Target),
+    /// Workspace relative path of a source file
+    File(String),
+}
+
+impl QueryItem {
+    fn target(&self) -> Option<&Target> {
+        match self {
+            Self::Target(target) => Some(target),
+            Self::File(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for QueryItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Target(target) => write!(f, "{target}"),
+            Self::File(path) => write!(f, "{path}"),
+        }
+    }
+}
+
+/// Results keyed by their string form so they come out sorted. Targets always
+/// contain a `:` and paths never do, so the two cannot collide.
+type TargetSet = BTreeMap<String, QueryItem>;
+
+fn insert(set: &mut TargetSet, target: &Target) {
+    set.insert(target.to_string(), QueryItem::Target(target.clone()));
+}
+
+fn kind_of(node: &TargetFiles) -> &'static str {
+    node.definition.kind().as_str()
+}
+
+fn collect_patterns<'a>(expression: &'a Expression, patterns: &mut Vec<&'a Target>) {
+    match expression {
+        Expression::Pattern(target) => patterns.push(target),
+        Expression::Unowned => {}
+        Expression::Deps(inner) | Expression::Kind(_, inner) => collect_patterns(inner, patterns),
+        Expression::ReverseDeps(left, right)
+        | Expression::SomePath(left, right)
+        | Expression::AllPaths(left, right)
+        | Expression::Union(left, right)
+        | Expression::Difference(left, right)
+        | Expression::Intersection(left, right) => {
+            collect_patterns(left, patterns);
+            collect_patterns(right, patterns);
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    graph: &'a BuildGraph,
+    root: &'a VfsPath,
+}
+
+impl Evaluator<'_> {
+    fn evaluate(&self, expression: &Expression) -> Result<TargetSet, DependencySortError> {
+        Ok(match expression {
+            Expression::Pattern(pattern) => {
+                let mut set = TargetSet::new();
+                for target in expand_target_pattern(pattern, self.root)? {
+                    insert(&mut set, &target);
+                }
+                set
+            }
+            Expression::Deps(inner) => self.dependency_closure(&self.evaluate(inner)?),
+            Expression::ReverseDeps(universe, targets) => {
+                let universe = self.dependency_closure(&self.evaluate(universe)?);
+                let mut set = TargetSet::new();
+                for (key, target) in self.dependent_closure(&self.evaluate(targets)?) {
+                    if universe.contains_key(&key) {
+                        set.insert(key, target);
+                    }
+                }
+                set
+            }
+            Expression::SomePath(from, to) => {
+                let mut set = TargetSet::new();
+                for target in self.some_path(&self.evaluate(from)?, &self.evaluate(to)?) {
+                    insert(&mut set, target);
+                }
+                set
+            }
+            Expression::AllPaths(from, to) => {
+                let from = self.dependency_closure(&self.evaluate(from)?);
+                let to = self.dependent_closure(&self.evaluate(to)?);
+                intersection(from, &to)
+            }
+            Expression::Kind(kind, inner) => self
+                .evaluate(inner)?
+                .into_iter()
+                .filter(|(_, item)| {
+                    item.target()
+                        .and_then(|target| self.graph.get(target))
+                        .is_some_and(|node| kind_of(node) == kind)
+                })
+                .collect(),
+            Expression::Unowned => find_unowned_files(self.root)?
+                .into_iter()
+                .map(|path| (path.clone(), QueryItem::File(path)))
+                .collect(),
+            Expression::Union(left, right) => {
+                let mut set = self.evaluate(left)?;
+                set.extend(self.evaluate(right)?);
+                set
+            }
+            Expression::Difference(left, right) => {
+                let right = self.evaluate(right)?;
+                self.evaluate(left)?
+                    .into_iter()
+                    .filter(|(key, _)| !right.contains_key(key))
+                    .collect()
+            }
+            Expression::Intersection(left, right) => {
+                intersection(self.evaluate(left)?, &self.evaluate(right)?)
+            }
+        })
+    }
+
+    /// The targets plus everything they transitively depend on.
+    fn dependency_closure(&self, targets: &TargetSet) -> TargetSet {
+        let mut set = targets.clone();
+        for target in targets.values().filter_map(QueryItem::target) {
+            for node in self.graph.transitive_dependencies(target) {
+                insert(&mut set, &node.target);
+            }
+        }
+        set
+    }
+
+    /// The targets plus everything that transitively depends on them.
+    fn dependent_closure(&self, targets: &TargetSet) -> TargetSet {
+        let mut set = targets.clone();
+        for target in targets.values().filter_map(QueryItem::target) {
+            for node in self.graph.transitive_dependents(target) {
+                insert(&mut set, &node.target);
+            }
+        }
+        set
+    }
+
+    /// Breadth first search from every target in `from`, so the path found is
+    /// one of the shortest. The path starts in `from` and ends in `to`.
+    fn some_path<'b>(&'b self, from: &'b TargetSet, to: &TargetSet) -> Vec<&'b Target> {
+        let mut parents: HashMap<String, Option<&Target>> = HashMap::new();
+        let mut queue = VecDeque::new();
+        for target in from.values().filter_map(QueryItem::target) {
+            parents.insert(target.to_string(), None);
+            queue.push_back(target);
+        }
+
+        while let Some(current) = queue.pop_front() {
+            if to.contains_key(&current.to_string()) {
+                let mut path = Vec::new();
+                let mut step = Some(current);
+                while let Some(target) = step {
+                    path.push(target);
+                    step = parents[&target.to_string()];
+                }
+                path.reverse();
+                return path;
+            }
+            for node in self.graph.direct_dependencies(current) {
+                if let Entry::Vacant(entry) = parents.entry(node.target.to_string()) {
+                    entry.insert(Some(current));
+                    queue.push_back(&node.target);
+                }
+            }
+        }
+        Vec::new()
+    }
+}
+
+fn intersection(left: TargetSet, right: &TargetSet) -> TargetSet {
+    left.into_iter()
+        .filter(|(key, _)| right.contains_key(key))
+        .collect()
+}
+
+/// Evaluates a query against the build files under `root`, with labels
+/// relative to `package`. Results are sorted by target or path, except that a
+/// query that is just `somepath` lists the path in dependency order.
+pub fn evaluate_query(
+    query: &str,
+    root: &VfsPath,
+    package: &str,
+) -> Result<Vec<QueryItem>, QueryError> {
+    let expression = parse_query(query, package).map_err(QueryError::ParseError)?;
+    let mut patterns = Vec::new();
+    collect_patterns(&expression, &mut patterns);
+
+    // Every function only looks at targets reachable from the patterns in the
+    // query, so their combined graph is all that needs resolving.
+    let mut nodes = Vec::new();
+    for pattern in patterns {
+        nodes.extend(
+            topologically_sort_dep_graph(pattern.clone(), root)
+                .map_err(QueryError::DependencySortError)?,
+        );
+    }
+    let graph = BuildGraph::new(nodes);
+
+    let evaluator = Evaluator {
+        graph: &graph,
+        root,
+    };
+    if let Expression::SomePath(from, to) = &expression {
+        let from = evaluator
+            .evaluate(from)
+            .map_err(QueryError::DependencySortError)?;
+        let to = evaluator
+            .evaluate(to)
+            .map_err(QueryError::DependencySortError)?;
+        return Ok(evaluator
+            .some_path(&from, &to)
+            .into_iter()
+            .map(|target| QueryItem::Target(target.clone()))
+            .collect());
+    }
+    let result = evaluator
+        .evaluate(&expression)
+        .map_err(QueryError::DependencySortError)?;
+    Ok(result.into_values().collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_utils::create_file::create_test_file;
+    use vfs::MemoryFS;
+
+    /// app:main -> lib:a -> lib:c
+    /// app:main -> lib:b -> lib:c
+    /// other:x -> lib:b
+    fn workspace() -> VfsPath {
+        let root: VfsPath = MemoryFS::new().into();
+        create_test_file(
+            &root,
+            "app/BUILD.toml",
+            b"
+            [[binary]]
+            name = \"main\"
+            entry_point = \"main.buri\"
+            files = [\"main.buri\"]
+            dependencies = [\"//lib:a\", \"//lib:b\"]
+            ",
+        );
+        create_test_file(
+            &root,
+            "lib/BUILD.toml",
+            b"
+            [[library]]
+            name = \"a\"
+            dependencies = [\"//lib:c\"]
+
+            [[library]]
+            name = \"b\"
+            dependencies = [\"//lib:c\"]
+
+            [[library]]
+            name = \"c\"
+            ",
+        );
+        create_test_file(
+            &root,
+            "other/BUILD.toml",
+            b"
+            [[library]]
+            name = \"x\"
+            dependencies = [\"//lib:b\"]
+            ",
+        );
+        root
+    }
+
+    fn query(query: &str) -> Vec<String> {
+        evaluate_query(query, &workspace(), "")
+            .unwrap()
+            .iter()
+            .map(|target| target.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn evaluates_a_single_target() {
+        assert_eq!(query("//lib:a"), Vec::from(["lib:a"]));
+    }
+
+    #[test]
+    fn evaluates_recursive_patterns() {
+        assert_eq!(query("lib:..."), Vec::from(["lib:a", "lib:b", "lib:c"]));
+    }
+
+    #[test]
+    fn deps_includes_the_target_and_everything_it_depends_on() {
+        assert_eq!(
+            query("deps(//app:main)"),
+            Vec::from(["app:main", "lib:a", "lib:b", "lib:c"])
+        );
+    }
+
+    #[test]
+    fn rdeps_finds_everything_in_the_universe_that_depends_on_a_target() {
+        assert_eq!(
+            query("rdeps(..., //lib:b)"),
+            Vec::from(["app:main", "lib:b", "other:x"])
+        );
+        assert_eq!(
+            query("rdeps(app:main, //lib:b)"),
+            Vec::from(["app:main", "lib:b"])
+        );
+    }
+
+    #[test]
+    fn somepath_finds_one_path() {
+        let path = query("somepath(app:main, lib:c)");
+        assert_eq!(path.len(), 3);
+        assert_eq!(path[0], "app:main");
+        assert!(path[1] == "lib:a" || path[1] == "lib:b");
+        assert_eq!(path[2], "lib:c");
+        // Inside other functions the path is a set like any other.
+        assert_eq!(
+            query("somepath(other:x, lib:c) + lib:a"),
+            Vec::from(["lib:a", "lib:b", "lib:c", "other:x"])
+        );
+    }
+
+    #[test]
+    fn somepath_is_empty_without_a_path() {
+        assert!(query("somepath(lib:c, app:main)").is_empty());
+    }
+
+    #[test]
+    fn allpaths_finds_every_target_on_any_path() {
+        assert_eq!(
+            query("allpaths(app:main, lib:c)"),
+            Vec::from(["app:main", "lib:a", "lib:b", "lib:c"])
+        );
+        assert_eq!(
+            query("allpaths(other:x, lib:c)"),
+            Vec::from(["lib:b", "lib:c", "other:x"])
+        );
+    }
+
+    #[test]
+    fn kind_filters_by_target_kind() {
+        assert_eq!(
+            query("kind(library, lib:...)"),
+            Vec::from(["lib:a", "lib:b", "lib:c"])
+        );
+        assert_eq!(query("kind(binary, ...)"), Vec::from(["app:main"]));
+        assert!(query("kind(test, ...)").is_empty());
+    }
+
+    #[test]
+    fn evaluates_set_operators() {
+        assert_eq!(query("lib:a + other:x"), Vec::from(["lib:a", "other:x"]));
+        assert_eq!(query("deps(app:main) - lib:..."), Vec::from(["app:main"]));
+        assert_eq!(
+            query("deps(app:main) ^ deps(other:x)"),
+            Vec::from(["lib:b", "lib:c"])
+        );
+    }
+
+    #[test]
+    fn unowned_lists_files_no_target_lists() {
+        let root = workspace();
+        create_test_file(&root, "lib/stray.buri", b"");
+        let query = |query: &str| {
+            evaluate_query(query, &root, "")
+                .unwrap()
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(query("unowned()"), Vec::from(["lib/stray.buri"]));
+        assert_eq!(query("unowned"), Vec::from(["lib/stray.buri"]));
+        assert_eq!(
+            query("unowned() + other:x"),
+            Vec::from(["lib/stray.buri", "other:x"])
+        );
+        assert!(query("kind(library, unowned())").is_empty());
+    }
+
+    #[test]
+    fn errors_on_unknown_targets() {
+        let result = evaluate_query("deps(lib:d)", &workspace(), "");
+        assert!(matches!(
+            result,
+            Err(QueryError::DependencySortError(
+                DependencySortError::TargetNotFoundInBuildFile(_, _)
+            ))
+        ));
+    }
+
+    #[test]
+    fn errors_on_invalid_queries() {
+        let result = evaluate_query("deps(", &workspace(), "");
+        assert!(matches!(result, Err(QueryError::ParseError(_))));
+    }
+}
@@ -0,0 +1,4 @@
+mod evaluate;
+pub mod parse;
+
+pub use evaluate::{evaluate_query, QueryError, QueryItem};
@@ -0,0 +1,463 @@
+use std::fmt;
+use target::{
+    parse::{parse_label, TargetParseError},
+    Target,
+};
+
+/// Target kinds that can be passed to `kind(...)`.
+pub const KINDS: [&str; 3] = ["library", "binary", "test"];
+
+#[derive(Debug, PartialEq)]
+pub enum Expression {
+    Pattern(Target),
+    /// The targets plus everything they transitively depend on.
+    Deps(Box<Expression>),
+    /// Universe, targets. Everything in the universe's dependency graph that
+    /// transitively depends on the targets.
+    ReverseDeps(Box<Expression>, Box<Expression>),
+    /// From, to. One dependency path between the two sets.
+    SomePath(Box<Expression>, Box<Expression>),
+    /// From, to. Every target on any dependency path between the two sets.
+    AllPaths(Box<Expression>, Box<Expression>),
+    Kind(String, Box<Expression>),
+    /// Source files in the workspace that no target lists.
+    Unowned,
+    Union(Box<Expression>, Box<Expression>),
+    Difference(Box<Expression>, Box<Expression>),
+    Intersection(Box<Expression>, Box<Expression>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum QueryParseError {
+    /// Byte offset, character
+    UnexpectedCharacter(usize, char),
+    UnexpectedEnd,
+    /// Byte offset, token
+    UnexpectedToken(usize, String),
+    UnknownFunction(String),
+    UnknownKind(String),
+    /// Function name, expected number of arguments, actual number of arguments
+    WrongNumberOfArguments(String, usize, usize),
+    /// Raw target pattern, parse error
+    InvalidTarget(String, TargetParseError),
+}
+
+impl fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnexpectedCharacter(offset, character) => {
+                write!(f, "unexpected character '{character}' at offset {offset}")
+            }
+            Self::UnexpectedEnd => write!(f, "unexpected end of query"),
+            Self::UnexpectedToken(offset, token) => {
+                write!(f, "unexpected '{token}' at offset {offset}")
+            }
+            Self::UnknownFunction(name) => write!(f, "unknown function {name}"),
+            Self::UnknownKind(kind) => write!(
+                f,
+                "unknown kind {kind}, expected one of: {}",
+                KINDS.join(", ")
+            ),
+            Self::WrongNumberOfArguments(name, expected, actual) => write!(
+                f,
+                "{name} takes {expected} argument(s) but {actual} were given"
+            ),
+            Self::InvalidTarget(target, error) => write!(f, "invalid target {target}: {error}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Word(String),
+    OpenParen,
+    CloseParen,
+    Comma,
+    Plus,
+    Minus,
+    Caret,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Word(word) => write!(f, "{word}"),
+            Self::OpenParen => write!(f, "("),
+            Self::CloseParen => write!(f, ")"),
+            Self::Comma => write!(f, ","),
+            Self::Plus => write!(f, "+"),
+            Self::Minus => write!(f, "-"),
+            Self::Caret => write!(f, "^"),
+        }
+    }
+}
+
+fn is_word_character(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '/' | ':' | '.')
+}
+
+fn tokenize(query: &str) -> Result<Vec<(usize, Token)>, QueryParseError> {
+    let mut tokens = Vec::new();
+    let mut characters = query.char_indices().peekable();
+    while let Some((offset, character)) = characters.next() {
+        let token = match character {
+            c if c.is_whitespace() => continue,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            ',' => Token::Comma,
+            '+' => Token::Plus,
+            '^' => Token::Caret,
+            // Dashes inside a word, such as `foo-bar:baz`, are consumed with
+            // the rest of the word. Anywhere else they mean difference.
+            '-' => Token::Minus,
+            c if is_word_character(c) => {
+                let mut word = c.to_string();
+                while let Some((_, next)) = characters.peek() {
+                    if !is_word_character(*next) {
+                        break;
+                    }
+                    word.push(*next);
+                    characters.next();
+                }
+                Token::Word(word)
+            }
+            c => return Err(QueryParseError::UnexpectedCharacter(offset, c)),
+        };
+        tokens.push((offset, token));
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    /// Package relative labels are resolved against.
+    package: &'a str,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Result<(usize, Token), QueryParseError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or(QueryParseError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), QueryParseError> {
+        let (offset, token) = self.next()?;
+        if token != expected {
+            return Err(QueryParseError::UnexpectedToken(offset, token.to_string()));
+        }
+        Ok(())
+    }
+
+    /// expression := term (("+" | "-" | "^") term)*
+    fn parse_expression(&mut self) -> Result<Expression, QueryParseError> {
+        let mut expression = self.parse_term()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Plus) => Expression::Union,
+                Some(Token::Minus) => Expression::Difference,
+                Some(Token::Caret) => Expression::Intersection,
+                _ => return Ok(expression),
+            };
+            self.position += 1;
+            let right = self.parse_term()?;
+            expression = operator(Box::new(expression), Box::new(right));
+        }
+    }
+
+    /// term := "(" expression ")" | word "(" arguments ")" | word
+    fn parse_term(&mut self) -> Result<Expression, QueryParseError> {
+        let (offset, token) = self.next()?;
+        match token {
+            Token::OpenParen => {
+                let expression = self.parse_expression()?;
+                self.expect(Token::CloseParen)?;
+                Ok(expression)
+            }
+            Token::Word(word) if self.peek() == Some(&Token::OpenParen) => {
+                self.position += 1;
+                self.parse_function(word)
+            }
+            // `unowned` on its own would otherwise be the target
+            // `unowned:unowned`, which is never what was meant. That target
+            // can still be written `//unowned`.
+            Token::Word(word) if word == "unowned" => Ok(Expression::Unowned),
+            Token::Word(word) => parse_pattern(&word, self.package),
+            token => Err(QueryParseError::UnexpectedToken(offset, token.to_string())),
+        }
+    }
+
+    fn parse_function(&mut self, name: String) -> Result<Expression, QueryParseError> {
+        let (expected_arguments, starts_with_kind) = match name.as_str() {
+            "unowned" => (0, false),
+            "deps" => (1, false),
+            "rdeps" | "somepath" | "allpaths" => (2, false),
+            "kind" => (2, true),
+            _ => return Err(QueryParseError::UnknownFunction(name)),
+        };
+
+        let mut kind = None;
+        let mut arguments = Vec::new();
+        loop {
+            // Functions such as `unowned()` take no arguments at all.
+            if arguments.is_empty() && kind.is_none() && self.peek() == Some(&Token::CloseParen) {
+                self.position += 1;
+                break;
+            }
+            if starts_with_kind && kind.is_none() {
+                let (offset, token) = self.next()?;
+                match token {
+                    Token::Word(word) if KINDS.contains(&word.as_str()) => kind = Some(word),
+                    Token::Word(word) => return Err(QueryParseError::UnknownKind(word)),
+                    token => {
+                        return Err(QueryParseError::UnexpectedToken(offset, token.to_string()))
+                    }
+                }
+            } else {
+                arguments.push(self.parse_expression()?);
+            }
+            let (offset, token) = self.next()?;
+            match token {
+                Token::Comma => continue,
+                Token::CloseParen => break,
+                token => return Err(QueryParseError::UnexpectedToken(offset, token.to_string())),
+            }
+        }
+
+        let actual_arguments = arguments.len() + usize::from(kind.is_some());
+        if actual_arguments != expected_arguments {
+            return Err(QueryParseError::WrongNumberOfArguments(
+                name,
+                expected_arguments,
+                actual_arguments,
+            ));
+        }
+
+        let mut arguments = arguments.into_iter().map(Box::new);
+        let mut argument = || arguments.next().unwrap();
+        Ok(match name.as_str() {
+            "deps" => Expression::Deps(argument()),
+            "rdeps" => Expression::ReverseDeps(argument(), argument()),
+            "somepath" => Expression::SomePath(argument(), argument()),
+            "allpaths" => Expression::AllPaths(argument(), argument()),
+            "unowned" => Expression::Unowned,
+            _ => Expression::Kind(kind.unwrap(), argument()),
+        })
+    }
+}
+
+fn parse_pattern(word: &str, package: &str) -> Result<Expression, QueryParseError> {
+    parse_label(word, package)
+        .map(Expression::Pattern)
+        .map_err(|error| QueryParseError::InvalidTarget(word.to_string(), error))
+}
+
+/// Parses a query. Labels in it are relative to `package`, unless they start
+/// with `//`.
+pub fn parse_query(query: &str, package: &str) -> Result<Expression, QueryParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(query)?,
+        position: 0,
+        package,
+    };
+    let expression = parser.parse_expression()?;
+    if let Some((offset, token)) = parser.tokens.get(parser.position) {
+        return Err(QueryParseError::UnexpectedToken(*offset, token.to_string()));
+    }
+    Ok(expression)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use target::parse::parse_target;
+
+    fn pattern(target: &str) -> Box<Expression> {
+        Box::new(Expression::Pattern(parse_target(target).unwrap()))
+    }
+
+    #[test]
+    fn parses_a_single_target() {
+        assert_eq!(parse_query("foo:bar", ""), Ok(*pattern("foo:bar")));
+    }
+
+    #[test]
+    fn parses_targets_relative_to_the_workspace_root() {
+        assert_eq!(parse_query("//foo:bar", ""), Ok(*pattern("foo:bar")));
+    }
+
+    #[test]
+    fn parses_targets_relative_to_the_package() {
+        assert_eq!(parse_query(":bar", "foo"), Ok(*pattern("foo:bar")));
+        assert_eq!(
+            parse_query("deps(bar:baz) + //qux:quux", "foo"),
+            Ok(Expression::Union(
+                Box::new(Expression::Deps(pattern("foo/bar:baz"))),
+                pattern("qux:quux")
+            ))
+        );
+    }
+
+    #[test]
+    fn parses_recursive_patterns() {
+        assert_eq!(parse_query("...", ""), Ok(*pattern("...")));
+        assert_eq!(parse_query("foo/bar:...", ""), Ok(*pattern("foo/bar:...")));
+    }
+
+    #[test]
+    fn parses_deps() {
+        assert_eq!(
+            parse_query("deps(//foo:bar)", ""),
+            Ok(Expression::Deps(pattern("foo:bar")))
+        );
+    }
+
+    #[test]
+    fn parses_two_argument_functions() {
+        assert_eq!(
+            parse_query("rdeps(..., //libs/x:y)", ""),
+            Ok(Expression::ReverseDeps(pattern("..."), pattern("libs/x:y")))
+        );
+        assert_eq!(
+            parse_query("somepath(a, b)", ""),
+            Ok(Expression::SomePath(pattern("a"), pattern("b")))
+        );
+        assert_eq!(
+            parse_query("allpaths(a, b)", ""),
+            Ok(Expression::AllPaths(pattern("a"), pattern("b")))
+        );
+    }
+
+    #[test]
+    fn parses_kind() {
+        assert_eq!(
+            parse_query("kind(library, foo:...)", ""),
+            Ok(Expression::Kind("library".to_string(), pattern("foo:...")))
+        );
+    }
+
+    #[test]
+    fn set_operators_are_left_associative() {
+        assert_eq!(
+            parse_query("a + b - c ^ d", ""),
+            Ok(Expression::Intersection(
+                Box::new(Expression::Difference(
+                    Box::new(Expression::Union(pattern("a"), pattern("b"))),
+                    pattern("c")
+                )),
+                pattern("d")
+            ))
+        );
+    }
+
+    #[test]
+    fn parentheses_group_expressions() {
+        assert_eq!(
+            parse_query("a - (b + c)", ""),
+            Ok(Expression::Difference(
+                pattern("a"),
+                Box::new(Expression::Union(pattern("b"), pattern("c")))
+            ))
+        );
+    }
+
+    #[test]
+    fn dashes_inside_words_are_part_of_the_target() {
+        assert_eq!(
+            parse_query("foo-bar:baz - qux", ""),
+            Ok(Expression::Difference(
+                pattern("foo-bar:baz"),
+                pattern("qux")
+            ))
+        );
+    }
+
+    #[test]
+    fn nests_functions() {
+        assert_eq!(
+            parse_query("deps(kind(library, foo:...)) ^ rdeps(..., bar)", ""),
+            Ok(Expression::Intersection(
+                Box::new(Expression::Deps(Box::new(Expression::Kind(
+                    "library".to_string(),
+                    pattern("foo:...")
+                )))),
+                Box::new(Expression::ReverseDeps(pattern("..."), pattern("bar")))
+            ))
+        );
+    }
+
+    #[test]
+    fn parses_functions_without_arguments() {
+        assert_eq!(parse_query("unowned()", ""), Ok(Expression::Unowned));
+        assert_eq!(
+            parse_query("unowned() - unowned", ""),
+            Ok(Expression::Difference(
+                Box::new(Expression::Unowned),
+                Box::new(Expression::Unowned)
+            ))
+        );
+        assert_eq!(parse_query("//unowned", ""), Ok(*pattern("unowned")));
+        assert_eq!(
+            parse_query("deps()", ""),
+            Err(QueryParseError::WrongNumberOfArguments(
+                "deps".to_string(),
+                1,
+                0
+            ))
+        );
+        assert_eq!(
+            parse_query("unowned(foo)", ""),
+            Err(QueryParseError::WrongNumberOfArguments(
+                "unowned".to_string(),
+                0,
+                1
+            ))
+        );
+    }
+
+    #[test]
+    fn errors_on_invalid_queries() {
+        assert_eq!(parse_query("", ""), Err(QueryParseError::UnexpectedEnd));
+        assert_eq!(
+            parse_query("deps(foo", ""),
+            Err(QueryParseError::UnexpectedEnd)
+        );
+        assert_eq!(
+            parse_query("foo bar", ""),
+            Err(QueryParseError::UnexpectedToken(4, "bar".to_string()))
+        );
+        assert_eq!(
+            parse_query("foo & bar", ""),
+            Err(QueryParseError::UnexpectedCharacter(4, '&'))
+        );
+        assert_eq!(
+            parse_query("depz(foo)", ""),
+            Err(QueryParseError::UnknownFunction("depz".to_string()))
+        );
+        assert_eq!(
+            parse_query("kind(rule, foo)", ""),
+            Err(QueryParseError::UnknownKind("rule".to_string()))
+        );
+        assert_eq!(
+            parse_query("deps(a, b)", ""),
+            Err(QueryParseError::WrongNumberOfArguments(
+                "deps".to_string(),
+                1,
+                2
+            ))
+        );
+        assert!(matches!(
+            parse_query("foo:bar:baz", ""),
+            Err(QueryParseError::InvalidTarget(_, _))
+        ));
+    }
+}
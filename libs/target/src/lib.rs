@@ -0,0 +1,4 @@
+pub mod parse;
+mod target_struct;
+
+pub use target_struct::*;
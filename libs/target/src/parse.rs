@@ -0,0 +1,403 @@
+use crate::{Index, Target, TargetName};
+use std::fmt;
+
+/// Offsets are in bytes into the text being parsed.
+#[derive(Debug, PartialEq)]
+pub enum TargetParseError {
+    TooShort,
+    /// Offset, offending character
+    IllegalCharacter(usize, char),
+    /// Offset of the trailing `:` or `/`
+    MissingTargetName(usize),
+    /// Offset of the slash following the unnamed directory
+    DirectoriesMustHaveAName(usize),
+    CannotStartWithASlash,
+    /// Offset of the `...`
+    ColonMustPrecedeRecursiveTarget(usize),
+}
+
+impl fmt::Display for TargetParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooShort => write!(f, "target cannot be empty"),
+            Self::IllegalCharacter(_, character) => {
+                write!(f, "target contains an illegal character `{character}`")
+            }
+            Self::MissingTargetName(_) => write!(f, "target is missing a name"),
+            Self::DirectoriesMustHaveAName(_) => write!(f, "directories must have a name"),
+            Self::CannotStartWithASlash => write!(f, "target cannot start with a slash"),
+            Self::ColonMustPrecedeRecursiveTarget(_) => write!(
+                f,
+                "recursive targets must be preceded by a colon, e.g. `foo:...`"
+            ),
+        }
+    }
+}
+
+impl TargetParseError {
+    /// Where in the parsed text the error is.
+    pub fn offset(&self) -> usize {
+        match self {
+            Self::TooShort | Self::CannotStartWithASlash => 0,
+            Self::IllegalCharacter(offset, _)
+            | Self::MissingTargetName(offset)
+            | Self::DirectoriesMustHaveAName(offset)
+            | Self::ColonMustPrecedeRecursiveTarget(offset) => *offset,
+        }
+    }
+
+    /// Renders `text`, the text that failed to parse, with a caret under the
+    /// error on the line below it.
+    pub fn underline(&self, text: &str) -> String {
+        let column = text
+            .get(..self.offset())
+            .map_or(0, |before| before.chars().count());
+        format!("{text}\n{}^", " ".repeat(column))
+    }
+
+    /// The same error for text that had `length` bytes in front of it.
+    pub(crate) fn shifted(self, length: usize) -> Self {
+        match self {
+            Self::IllegalCharacter(offset, character) => {
+                Self::IllegalCharacter(offset + length, character)
+            }
+            Self::MissingTargetName(offset) => Self::MissingTargetName(offset + length),
+            Self::DirectoriesMustHaveAName(offset) => {
+                Self::DirectoriesMustHaveAName(offset + length)
+            }
+            Self::ColonMustPrecedeRecursiveTarget(offset) => {
+                Self::ColonMustPrecedeRecursiveTarget(offset + length)
+            }
+            Self::TooShort | Self::CannotStartWithASlash => self,
+        }
+    }
+}
+
+fn is_valid_part_character(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+pub fn parse_target(str: &str) -> Result<Target, TargetParseError> {
+    if str.is_empty() {
+        return Err(TargetParseError::TooShort);
+    }
+    if str.ends_with(':') || str.ends_with('/') {
+        return Err(TargetParseError::MissingTargetName(str.len() - 1));
+    }
+    if str.ends_with("...") {
+        return parse_recursive_target(str);
+    }
+    parse_specific_target(str)
+}
+
+/// Spells `target` the one way `Target` displays it, `directories:name`, so
+/// `foo` and `foo:foo` both become `foo:foo`, and `foo/bar` and `foo/bar:bar`
+/// both become `foo/bar:bar`.
+pub fn canonicalize(target: &str) -> Result<String, TargetParseError> {
+    parse_target(target).map(|target| target.to_string())
+}
+
+/// Parses a label written inside `package`, a workspace relative directory
+/// such as `foo/bar`. Labels starting with `//` are absolute. Anything else is
+/// relative to `package`, so in `foo` the label `:bar` is `foo:bar` and
+/// `bar:baz` is `foo/bar:baz`.
+pub fn parse_label(label: &str, package: &str) -> Result<Target, TargetParseError> {
+    Ok(label.parse::<Target>()?.in_package(package))
+}
+
+fn parse_specific_target(str: &str) -> Result<Target, TargetParseError> {
+    let mut target_start_index = 0;
+    let mut directories_end = str.len();
+    let mut has_set_target_start = false;
+    let mut has_seen_slash = false;
+    let mut is_previous_char_slash = false;
+    // Go in reverse in case the target name is implicitly the directory.
+    for (index, char) in str.char_indices().rev() {
+        match char {
+            c if is_valid_part_character(c) => {}
+            ':' => {
+                if has_seen_slash || has_set_target_start {
+                    return Err(TargetParseError::IllegalCharacter(index, char));
+                }
+                target_start_index = index + 1;
+                has_set_target_start = true;
+                directories_end = index;
+            }
+            '/' => {
+                if is_previous_char_slash {
+                    return Err(TargetParseError::DirectoriesMustHaveAName(index + 1));
+                }
+                if index == 0 {
+                    return Err(TargetParseError::CannotStartWithASlash);
+                }
+                if !has_set_target_start {
+                    target_start_index = index + 1;
+                    has_set_target_start = true;
+                }
+                has_seen_slash = true;
+            }
+            _ => return Err(TargetParseError::IllegalCharacter(index, char)),
+        }
+        is_previous_char_slash = char == '/';
+    }
+
+    Ok(Target {
+        name: TargetName::Specific(target_start_index as Index),
+        directories_end: directories_end as Index,
+        raw_text: str.to_string(),
+        relative: false,
+    })
+}
+
+fn parse_recursive_target(str: &str) -> Result<Target, TargetParseError> {
+    // `foo/...` is another spelling of `foo:...`. Both are the same length,
+    // so error offsets still point into the text as written.
+    if let Some(directories) = str.strip_suffix("/...") {
+        if directories.is_empty() {
+            return Err(TargetParseError::CannotStartWithASlash);
+        }
+        return parse_recursive_target(&format!("{directories}:..."));
+    }
+    if str == "..." {
+        return Ok(Target {
+            name: TargetName::Recursive,
+            directories_end: 0,
+            raw_text: str.to_string(),
+            relative: false,
+        });
+    }
+    if !str.ends_with(":...") {
+        return Err(TargetParseError::ColonMustPrecedeRecursiveTarget(
+            str.len() - 3,
+        ));
+    }
+    let directories_slice = &str[..str.len() - 4];
+    if directories_slice.ends_with('/') {
+        return Err(TargetParseError::DirectoriesMustHaveAName(
+            directories_slice.len() - 1,
+        ));
+    } else if directories_slice.starts_with('/') {
+        return Err(TargetParseError::CannotStartWithASlash);
+    }
+    let mut was_previous_char_slash = false;
+    for (index, char) in directories_slice.char_indices() {
+        if char == '/' && was_previous_char_slash {
+            return Err(TargetParseError::DirectoriesMustHaveAName(index));
+        }
+        was_previous_char_slash = char == '/';
+        if char != '/' && !is_valid_part_character(char) {
+            return Err(TargetParseError::IllegalCharacter(index, char));
+        }
+    }
+    Ok(Target {
+        name: TargetName::Recursive,
+        directories_end: directories_slice.len() as Index,
+        raw_text: str.to_string(),
+        relative: false,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_specific_target_names() {
+        let tests = [
+            ["foo", "foo"],
+            ["foo:bar", "bar"],
+            ["foo/bar", "bar"],
+            ["foo/bar/foobar", "foobar"],
+        ];
+        for test in tests.iter() {
+            let target = parse_target(test[0]).unwrap();
+            assert_eq!(target.name(), test[1].to_string());
+        }
+    }
+
+    #[test]
+    fn test_specific_target_directories() {
+        let tests = [
+            ["foo", "foo"],
+            ["foo:bar", "foo"],
+            ["foo/bar", "foo/bar"],
+            ["foo/bar/baz", "foo/bar/baz"],
+        ];
+        for test in tests.iter() {
+            let target = parse_target(test[0]).unwrap();
+            assert_eq!(target.get_directories(), test[1]);
+        }
+    }
+
+    #[test]
+    fn test_recursive_target_names() {
+        let tests = [
+            ["...", "..."],
+            [":...", "..."],
+            ["foo:...", "..."],
+            ["foo/bar:...", "..."],
+            ["foo/bar/baz:...", "..."],
+        ];
+        for test in tests.iter() {
+            let target = parse_target(test[0]).unwrap();
+            assert_eq!(target.name(), test[1].to_string());
+        }
+    }
+
+    #[test]
+    fn test_recursive_target_directories() {
+        let tests = [
+            ["...", ""],
+            [":...", ""],
+            ["foo:...", "foo"],
+            ["foo/bar:...", "foo/bar"],
+            ["foo/bar/baz:...", "foo/bar/baz"],
+        ];
+        for test in tests.iter() {
+            let target = parse_target(test[0]).unwrap();
+            assert_eq!(target.get_directories(), test[1]);
+        }
+    }
+
+    #[test]
+    fn accepts_a_slash_before_recursive_targets() {
+        let tests = [["foo/...", "foo:..."], ["foo/bar/...", "foo/bar:..."]];
+        for test in tests.iter() {
+            assert_eq!(canonicalize(test[0]).unwrap(), test[1], "{test:?}");
+        }
+        assert_eq!(
+            parse_label("//apps/...", "foo").unwrap().to_string(),
+            "apps:..."
+        );
+        assert_eq!(
+            parse_label("bar/...", "foo").unwrap().to_string(),
+            "foo/bar:..."
+        );
+        assert_eq!(
+            parse_target("/...").unwrap_err(),
+            TargetParseError::CannotStartWithASlash
+        );
+        assert_eq!(
+            parse_target("f$o/...").unwrap_err(),
+            TargetParseError::IllegalCharacter(1, '$')
+        );
+    }
+
+    #[test]
+    fn errors_on_invalid_targets() {
+        let tests = [
+            "",
+            ":",
+            "\\",
+            "hello world",
+            "foo/bar...",
+            "foo/bar:baz...",
+            "foo/bar:baz:...",
+            "foo/.../bar",
+            "foo ",
+            "/hello",
+            "hello/",
+            "foo/bar:baz/qux",
+            "//hello",
+            "...:foo",
+            "foo:bar:baz",
+            "foo:bar/baz",
+            "foo::bar",
+            "  ...",
+            "foo:  ...",
+            "foo:bar:  ...",
+            "foo...",
+        ];
+        for test in tests.iter() {
+            let result = parse_target(test);
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn reports_where_targets_are_invalid() {
+        let tests = [
+            ("", TargetParseError::TooShort),
+            ("foo:", TargetParseError::MissingTargetName(3)),
+            ("foo:b$d", TargetParseError::IllegalCharacter(5, '$')),
+            ("föo:bar", TargetParseError::IllegalCharacter(1, 'ö')),
+            ("foo:bar:baz", TargetParseError::IllegalCharacter(3, ':')),
+            ("foo//bar", TargetParseError::DirectoriesMustHaveAName(4)),
+            ("/foo", TargetParseError::CannotStartWithASlash),
+            (
+                "foo...",
+                TargetParseError::ColonMustPrecedeRecursiveTarget(3),
+            ),
+            (
+                "foo//bar:...",
+                TargetParseError::DirectoriesMustHaveAName(4),
+            ),
+            ("f$o:...", TargetParseError::IllegalCharacter(1, '$')),
+        ];
+        for (target, error) in tests {
+            assert_eq!(parse_target(target).unwrap_err(), error, "{target:?}");
+        }
+        assert_eq!(
+            parse_label("//foo:b$d", "bar").unwrap_err(),
+            TargetParseError::IllegalCharacter(7, '$')
+        );
+    }
+
+    #[test]
+    fn underlines_the_error() {
+        let error = parse_target("föo:b$d").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "target contains an illegal character `$`"
+        );
+        assert_eq!(error.underline("föo:b$d"), "föo:b$d\n     ^");
+    }
+
+    #[test]
+    fn canonicalizes_equivalent_spellings() {
+        let tests = [
+            ["foo", "foo:foo"],
+            ["foo:foo", "foo:foo"],
+            ["foo/bar", "foo/bar:bar"],
+            ["foo/bar:bar", "foo/bar:bar"],
+            [":foo", ":foo"],
+            ["foo:...", "foo:..."],
+        ];
+        for test in tests.iter() {
+            assert_eq!(canonicalize(test[0]).unwrap(), test[1], "{test:?}");
+        }
+        assert!(canonicalize("foo:").is_err());
+    }
+
+    #[test]
+    fn test_labels() {
+        let tests = [
+            ["//foo:bar", "baz", "foo:bar"],
+            ["//...", "baz", ":..."],
+            [":bar", "foo", "foo:bar"],
+            ["bar:baz", "foo", "foo/bar:baz"],
+            ["bar", "foo", "foo/bar:bar"],
+            ["...", "foo", "foo:..."],
+            ["bar:...", "foo/qux", "foo/qux/bar:..."],
+            ["bar:baz", "", "bar:baz"],
+            [":bar", "", ":bar"],
+        ];
+        for test in tests.iter() {
+            let target = parse_label(test[0], test[1]).unwrap();
+            assert_eq!(target.to_string(), test[2], "{test:?}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_labels() {
+        let tests = [
+            ["/foo:bar", "baz"],
+            ["///foo:bar", "baz"],
+            ["", "foo"],
+            ["foo::bar", "baz"],
+        ];
+        for test in tests.iter() {
+            assert!(parse_label(test[0], test[1]).is_err(), "{test:?}");
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use crate::parse::{parse_target, TargetParseError};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+pub(crate) type Index = u16;
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum TargetName {
+    /// Target name starts Index characters from the end of the raw string
+    /// not including the colon.
+    Specific(Index),
+    Recursive,
+}
+
+#[derive(Debug, Clone)]
+// Everything is saved as indices to reduce memory and heap allocations.
+pub struct Target {
+    pub(crate) name: TargetName,
+    pub(crate) directories_end: Index,
+    pub(crate) raw_text: String,
+    /// Set for labels that have not been resolved against the package they
+    /// were written in yet. See `in_package`.
+    pub(crate) relative: bool,
+}
+
+/// Targets are displayed in their canonical form, `directories:name`, so
+/// equivalent spellings display the same.
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}",
+            &self.raw_text[..self.directories_end as usize],
+            self.name()
+        )
+    }
+}
+
+// Targets are compared by what they refer to, so `foo/bar` and
+// `foo/bar:bar` are the same target.
+impl PartialEq for Target {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Target {}
+
+impl PartialOrd for Target {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Target {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.get_directories(), self.name(), self.relative).cmp(&(
+            other.get_directories(),
+            other.name(),
+            other.relative,
+        ))
+    }
+}
+
+impl Hash for Target {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.get_directories().hash(state);
+        self.name().hash(state);
+        self.relative.hash(state);
+    }
+}
+
+/// Parses a label as written in a build file. Labels starting with `//` are
+/// absolute, anything else is relative until resolved with `in_package`.
+impl FromStr for Target {
+    type Err = TargetParseError;
+
+    fn from_str(label: &str) -> Result<Self, Self::Err> {
+        match label.strip_prefix("//") {
+            Some(absolute) => parse_target(absolute).map_err(|error| error.shifted(2)),
+            None => Ok(Target {
+                relative: true,
+                ..parse_target(label)?
+            }),
+        }
+    }
+}
+
+impl Serialize for Target {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.relative {
+            serializer.collect_str(self)
+        } else {
+            serializer.collect_str(&format_args!("//{self}"))
+        }
+    }
+}
+
+struct TargetVisitor;
+
+impl de::Visitor<'_> for TargetVisitor {
+    type Value = Target;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a target label")
+    }
+
+    // Parsing while visiting, rather than after deserializing a string, lets
+    // formats like TOML attach the position of the label to the error.
+    fn visit_str<E: de::Error>(self, label: &str) -> Result<Target, E> {
+        label
+            .parse()
+            .map_err(|error| E::custom(format!("invalid target `{label}`: {error}")))
+    }
+}
+
+impl<'de> Deserialize<'de> for Target {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(TargetVisitor)
+    }
+}
+
+impl Target {
+    /// Resolves a label written inside `package`, a workspace relative
+    /// directory such as `foo/bar`. Absolute targets are returned as is.
+    ///
+    /// Panics if `package` could not be written in a label, e.g. `my dir`.
+    /// The directories of a parsed target are always a valid package.
+    pub fn in_package(&self, package: &str) -> Target {
+        if !self.relative || package.is_empty() {
+            return Target {
+                relative: false,
+                ..self.clone()
+            };
+        }
+        let resolved = if self.directories_end == 0 {
+            format!("{package}:{}", self.name())
+        } else {
+            format!("{package}/{}:{}", self.get_directories(), self.name())
+        };
+        parse_target(&resolved).expect("a valid package joined with a valid label is valid")
+    }
+
+    /// The canonical label for this resolved target in the build file of
+    /// `package`: `:name` within the package and `//directories:name`
+    /// everywhere else.
+    pub fn label_in(&self, package: &str) -> String {
+        if self.get_directories() == package {
+            format!(":{}", self.name())
+        } else {
+            format!("//{self}")
+        }
+    }
+
+    pub fn get_directories(&self) -> &str {
+        &self.raw_text[..self.directories_end as usize]
+    }
+
+    pub fn is_recursive(&self) -> bool {
+        self.name == TargetName::Recursive
+    }
+
+    /// Whether `target` is matched by this target used as a pattern. Specific
+    /// targets only match themselves, and recursive targets match every target
+    /// in their directory or below it.
+    pub fn matches(&self, target: &Target) -> bool {
+        match self.name {
+            TargetName::Recursive => {
+                let directories = self.get_directories();
+                directories.is_empty()
+                    || target.get_directories() == directories
+                    || target
+                        .get_directories()
+                        .strip_prefix(directories)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            }
+            TargetName::Specific(_) => self.to_string() == target.to_string(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match &self.name {
+            TargetName::Specific(index) => &self.raw_text[*index as usize..],
+            TargetName::Recursive => "...",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse::parse_target, Target};
+
+    #[test]
+    fn test_target() {
+        let target = parse_target("foo/bar:test").unwrap();
+        assert_eq!(target.to_string(), "foo/bar:test");
+    }
+
+    #[test]
+    fn test_no_directories() {
+        let target = parse_target(":test").unwrap();
+        assert_eq!(target.to_string(), ":test");
+    }
+
+    #[test]
+    fn test_equality() {
+        assert_eq!(
+            parse_target("foo/bar").unwrap(),
+            parse_target("foo/bar:bar").unwrap()
+        );
+        assert_ne!(
+            parse_target("foo:bar").unwrap(),
+            parse_target("foo:baz").unwrap()
+        );
+        assert_ne!(
+            "foo:bar".parse::<Target>().unwrap(),
+            "//foo:bar".parse::<Target>().unwrap()
+        );
+        assert!(parse_target("foo:bar").unwrap() < parse_target("foo/bar:a").unwrap());
+    }
+
+    #[test]
+    fn test_from_str() {
+        let tests = [
+            ["//foo:bar", "foo", "foo:bar"],
+            [":bar", "foo", "foo:bar"],
+            ["bar", "foo", "foo/bar:bar"],
+            ["...", "foo", "foo:..."],
+            ["bar:...", "foo", "foo/bar:..."],
+            [":bar", "", ":bar"],
+        ];
+        for test in tests.iter() {
+            let target = test[0].parse::<Target>().unwrap();
+            assert_eq!(target.in_package(test[1]).to_string(), test[2], "{test:?}");
+        }
+        assert!("//foo:b$d".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn test_label_in() {
+        let tests = [
+            ["foo:bar", "foo", ":bar"],
+            ["foo/bar", "foo", "//foo/bar:bar"],
+            ["foo:bar", "baz", "//foo:bar"],
+            [":bar", "", ":bar"],
+            ["foo:...", "foo", ":..."],
+        ];
+        for test in tests.iter() {
+            let target = parse_target(test[0]).unwrap();
+            assert_eq!(target.label_in(test[1]), test[2], "{test:?}");
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let tests = [
+            ["...", "foo:bar", "true"],
+            ["foo:...", "foo:bar", "true"],
+            ["foo:...", "foo/baz:bar", "true"],
+            ["foo:...", "foobar:baz", "false"],
+            ["foo/bar:...", "foo:bar", "false"],
+            ["foo:bar", "foo:bar", "true"],
+            ["foo/bar", "foo/bar:bar", "true"],
+            ["foo:bar", "foo:baz", "false"],
+        ];
+        for test in tests.iter() {
+            let pattern = parse_target(test[0]).unwrap();
+            let target = parse_target(test[1]).unwrap();
+            assert_eq!(pattern.matches(&target).to_string(), test[2], "{test:?}");
+        }
+    }
+
+    #[test]
+    fn test_is_recursive() {
+        assert!(parse_target("...").unwrap().is_recursive());
+        assert!(parse_target("foo/bar:...").unwrap().is_recursive());
+        assert!(!parse_target("foo/bar").unwrap().is_recursive());
+        assert!(!parse_target("foo:bar").unwrap().is_recursive());
+    }
+}